@@ -0,0 +1,356 @@
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::credentials::{CredentialStore, GITHUB_TOKEN_KEY};
+use crate::git_commands::GitError;
+use crate::hosting::{Host, HostedUser};
+use crate::repo_list;
+
+const ACCOUNTS_FILE: &str = "accounts.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    // Local id; also names the account's token in the credential store
+    pub id: String,
+    // Instance the account lives on; accounts saved before Enterprise
+    // support are github.com ones
+    #[serde(default)]
+    pub host: Host,
+    // The host's numeric user id, so signing in again updates the account
+    // instead of adding a second one
+    pub user_id: u64,
+    pub login: String,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    // Scopes granted to the token, empty where the host doesn't say
+    pub scopes: Vec<String>,
+    pub added_at: i64,
+    // Filled in on listing, whatever was saved is ignored
+    #[serde(skip_deserializing, default)]
+    pub is_default: bool,
+}
+
+// Ties a repository, or one of its remotes, to an account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountBinding {
+    pub repo_path: String,
+    // None binds every remote without a binding of its own
+    pub remote: Option<String>,
+    pub account_id: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AccountsFile {
+    accounts: Vec<Account>,
+    default_account: Option<String>,
+    bindings: Vec<AccountBinding>,
+}
+
+// Signed-in accounts on GitHub, GitLab and Gitea hosts, and which repository
+// uses which. Profiles and bindings live in accounts.json, tokens only in the
+// credential store.
+pub struct AccountRegistry {
+    file: PathBuf,
+    accounts: Vec<Account>,
+    default_account: Option<String>,
+    bindings: Vec<AccountBinding>,
+    credentials: Arc<dyn CredentialStore>,
+}
+
+impl AccountRegistry {
+    pub fn load(config_dir: &Path, credentials: Arc<dyn CredentialStore>) -> Result<Self, GitError> {
+        let file = config_dir.join(ACCOUNTS_FILE);
+        let contents = if file.exists() {
+            let content = fs::read_to_string(&file)?;
+            serde_json::from_str::<AccountsFile>(&content)
+                .map_err(|e| GitError::Custom(format!("Failed to parse {}: {}", file.display(), e)))?
+        } else {
+            AccountsFile::default()
+        };
+        // Bindings saved before their paths were canonicalized; where two
+        // spellings of a path were bound, the later binding wins
+        let mut bindings: Vec<AccountBinding> = Vec::new();
+        for mut binding in contents.bindings {
+            binding.repo_path = repo_key(&binding.repo_path);
+            bindings.retain(|bound| !(bound.repo_path == binding.repo_path && bound.remote == binding.remote));
+            bindings.push(binding);
+        }
+        Ok(AccountRegistry {
+            file,
+            accounts: contents.accounts,
+            default_account: contents.default_account,
+            bindings,
+            credentials,
+        })
+    }
+
+    fn save(&self) -> Result<(), GitError> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = AccountsFile {
+            accounts: self.accounts.clone(),
+            default_account: self.default_account.clone(),
+            bindings: self.bindings.clone(),
+        };
+        let content = serde_json::to_string_pretty(&contents)
+            .map_err(|e| GitError::Custom(e.to_string()))?;
+
+        let tmp = self.file.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<Account> {
+        self.accounts.iter()
+            .cloned()
+            .map(|mut account| {
+                account.is_default = self.default_account.as_deref() == Some(account.id.as_str());
+                account
+            })
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Account> {
+        self.list().into_iter().find(|account| account.id == id)
+    }
+
+    pub fn default_account(&self) -> Option<Account> {
+        self.default_account.as_deref().and_then(|id| self.get(id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    // Adds the account or, if that user of the host is already known, refreshes
+    // its profile and token. The first account becomes the default.
+    pub fn add(&mut self, user: HostedUser, host: Host, token: &str) -> Result<Account, GitError> {
+        let existing = self.accounts.iter_mut()
+            .find(|account| account.user_id == user.id && account.host.web_url == host.web_url);
+        let id = match existing {
+            Some(account) => {
+                account.host = host;
+                account.login = user.login;
+                account.name = user.name;
+                account.avatar_url = user.avatar_url;
+                account.scopes = user.scopes;
+                account.id.clone()
+            }
+            None => {
+                let id = uuid::Uuid::new_v4().to_string();
+                self.accounts.push(Account {
+                    id: id.clone(),
+                    host,
+                    user_id: user.id,
+                    login: user.login,
+                    name: user.name,
+                    avatar_url: user.avatar_url,
+                    scopes: user.scopes,
+                    added_at: unix_now(),
+                    is_default: false,
+                });
+                id
+            }
+        };
+
+        self.credentials.set(&token_key(&id), token)
+            .map_err(|e| GitError::Custom(e.to_string()))?;
+        if self.default_account.is_none() {
+            self.default_account = Some(id.clone());
+        }
+        self.save()?;
+        self.get(&id).ok_or_else(|| GitError::Custom("Account vanished while saving".into()))
+    }
+
+    // Forgets the account, its token and its bindings. If it was the default,
+    // the oldest remaining account takes over.
+    pub fn remove(&mut self, id: &str) -> Result<(), GitError> {
+        self.require(id)?;
+        self.credentials.delete(&token_key(id))
+            .map_err(|e| GitError::Custom(e.to_string()))?;
+        self.accounts.retain(|account| account.id != id);
+        self.bindings.retain(|binding| binding.account_id != id);
+        if self.default_account.as_deref() == Some(id) {
+            self.default_account = self.accounts.first().map(|account| account.id.clone());
+        }
+        self.save()
+    }
+
+    pub fn set_default(&mut self, id: &str) -> Result<(), GitError> {
+        self.require(id)?;
+        self.default_account = Some(id.to_string());
+        self.save()
+    }
+
+    pub fn token(&self, id: &str) -> Result<Option<String>, GitError> {
+        self.credentials.get(&token_key(id))
+            .map_err(|e| GitError::Custom(e.to_string()))
+    }
+
+    // Binds the repository (or only `remote`) to an account; None removes the binding
+    pub fn bind(&mut self, repo_path: &str, remote: Option<String>, account_id: Option<String>) -> Result<(), GitError> {
+        if let Some(account_id) = &account_id {
+            self.require(account_id)?;
+        }
+        let repo_path = repo_key(repo_path);
+        self.bindings.retain(|binding| !(binding.repo_path == repo_path && binding.remote == remote));
+        if let Some(account_id) = account_id {
+            self.bindings.push(AccountBinding { repo_path, remote, account_id });
+        }
+        self.save()
+    }
+
+    pub fn bindings(&self, repo_path: &str) -> Vec<AccountBinding> {
+        let repo_path = repo_key(repo_path);
+        self.bindings.iter()
+            .filter(|binding| binding.repo_path == repo_path)
+            .cloned()
+            .collect()
+    }
+
+    // The account to use for `remote` of a repository: the remote's own
+    // binding, then the repository's, then the default account
+    pub fn account_for(&self, repo_path: &str, remote: Option<&str>) -> Option<Account> {
+        let repo_path = repo_key(repo_path);
+        let bound = |remote: Option<&str>| {
+            self.bindings.iter()
+                .find(|binding| binding.repo_path == repo_path && binding.remote.as_deref() == remote)
+                .and_then(|binding| self.get(&binding.account_id))
+        };
+        remote.and_then(|remote| bound(Some(remote)))
+            .or_else(|| bound(None))
+            .or_else(|| self.default_account())
+    }
+
+    // account_for with the account's token
+    pub fn token_for(&self, repo_path: &str, remote: Option<&str>) -> Result<Option<(Account, String)>, GitError> {
+        let account = match self.account_for(repo_path, remote) {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+        Ok(self.token(&account.id)?.map(|token| (account, token)))
+    }
+
+    // Drops the bindings of a repository that was deleted or forgotten
+    pub fn forget_repository(&mut self, repo_path: &str) -> Result<(), GitError> {
+        let repo_path = repo_key(repo_path);
+        self.bindings.retain(|binding| binding.repo_path != repo_path);
+        self.save()
+    }
+
+    // The single token stored before accounts existed, until it has been
+    // turned into an account
+    pub fn legacy_token(&self) -> Result<Option<String>, GitError> {
+        self.credentials.get(GITHUB_TOKEN_KEY)
+            .map_err(|e| GitError::Custom(e.to_string()))
+    }
+
+    pub fn clear_legacy_token(&self) -> Result<(), GitError> {
+        self.credentials.delete(GITHUB_TOKEN_KEY)
+            .map_err(|e| GitError::Custom(e.to_string()))
+    }
+
+    fn require(&self, id: &str) -> Result<(), GitError> {
+        if self.accounts.iter().any(|account| account.id == id) {
+            Ok(())
+        } else {
+            Err(GitError::Custom(format!("Unknown account: {}", id)))
+        }
+    }
+}
+
+// Bindings are keyed by canonical path, like open repositories, so that any
+// spelling of a repository's path finds them. Paths that no longer exist
+// keep their normalized form.
+fn repo_key(repo_path: &str) -> String {
+    match fs::canonicalize(repo_path) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => repo_list::normalize(repo_path),
+    }
+}
+
+// Keeps its original name so tokens stored before other providers still resolve
+fn token_key(account_id: &str) -> String {
+    format!("github_token:{}", account_id)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::EncryptedFileStore;
+    use crate::hosting::ProviderKind;
+
+    fn registry(config_dir: &Path) -> AccountRegistry {
+        let store = EncryptedFileStore::with_key(config_dir.join("credentials.vault"), [7; 32]);
+        AccountRegistry::load(config_dir, Arc::new(store)).unwrap()
+    }
+
+    fn octocat() -> HostedUser {
+        HostedUser { id: 1, login: "octocat".into(), name: None, avatar_url: None, scopes: Vec::new() }
+    }
+
+    #[test]
+    fn finds_bindings_under_any_spelling_of_the_path() {
+        let config = tempfile::tempdir().unwrap();
+        let repo = tempfile::tempdir().unwrap();
+        let repo_path = repo.path().canonicalize().unwrap();
+        let mut accounts = registry(config.path());
+        let account = accounts.add(octocat(), Host::new(ProviderKind::GitHub, None, None), "token").unwrap();
+
+        accounts.bind(&format!("{}/", repo_path.display()), Some("origin".into()), Some(account.id.clone())).unwrap();
+
+        let dotted = repo_path.join(".").to_string_lossy().into_owned();
+        let (bound, token) = accounts.token_for(&dotted, Some("origin")).unwrap().unwrap();
+        assert_eq!(bound.id, account.id);
+        assert_eq!(token, "token");
+        assert_eq!(accounts.bindings(&repo_path.to_string_lossy()).len(), 1);
+
+        #[cfg(unix)]
+        {
+            let link = config.path().join("link");
+            std::os::unix::fs::symlink(&repo_path, &link).unwrap();
+            let bound = accounts.account_for(&link.to_string_lossy(), Some("origin")).unwrap();
+            assert_eq!(bound.id, account.id);
+        }
+
+        accounts.forget_repository(&dotted).unwrap();
+        assert!(accounts.bindings(&repo_path.to_string_lossy()).is_empty());
+    }
+
+    #[test]
+    fn rekeys_bindings_saved_under_other_spellings() {
+        let config = tempfile::tempdir().unwrap();
+        let repo = tempfile::tempdir().unwrap();
+        let repo_path = repo.path().canonicalize().unwrap().to_string_lossy().into_owned();
+        let binding = |repo_path: String, account_id: &str| AccountBinding {
+            repo_path,
+            remote: Some("origin".into()),
+            account_id: account_id.into(),
+        };
+        let saved = AccountsFile {
+            accounts: Vec::new(),
+            default_account: None,
+            bindings: vec![
+                binding(format!("{}/", repo_path), "first"),
+                binding(format!("{}/./", repo_path), "second"),
+            ],
+        };
+        fs::write(config.path().join(ACCOUNTS_FILE), serde_json::to_string(&saved).unwrap()).unwrap();
+
+        let accounts = registry(config.path());
+
+        assert_eq!(accounts.bindings(&repo_path), vec![binding(repo_path.clone(), "second")]);
+    }
+}
@@ -0,0 +1,295 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Service name secrets are filed under in the OS keyring
+const KEYRING_SERVICE: &str = "simplegit";
+
+const VAULT_FILE: &str = "credentials.vault";
+const VAULT_KEY_FILE: &str = "credentials.key";
+const NONCE_LEN: usize = 12;
+
+// Key of the single GitHub token kept before multiple accounts; only read
+// to migrate it into an account
+pub const GITHUB_TOKEN_KEY: &str = "github_token";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CredentialError {
+    #[error("Keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Credential vault is corrupt or was encrypted with a different key")]
+    Decrypt,
+    #[error("Credential vault error: {0}")]
+    Format(String),
+}
+
+// Where secrets (OAuth tokens, later passphrases) live. Nothing else in the
+// app should hold a secret longer than a single request needs it.
+pub trait CredentialStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, CredentialError>;
+    fn set(&self, key: &str, secret: &str) -> Result<(), CredentialError>;
+    fn delete(&self, key: &str) -> Result<(), CredentialError>;
+    // Short backend name, logged at startup
+    fn backend(&self) -> &'static str;
+}
+
+// Secret Service on Linux, Keychain on macOS, Credential Manager on Windows
+pub struct KeyringStore {
+    service: String,
+}
+
+impl KeyringStore {
+    pub fn new(service: &str) -> Self {
+        KeyringStore { service: service.to_string() }
+    }
+
+    // Whether a keyring daemon is reachable; headless sessions often lack one
+    pub fn is_available(&self) -> bool {
+        match keyring::Entry::new(&self.service, "availability-probe").and_then(|entry| entry.get_password()) {
+            Ok(_) | Err(keyring::Error::NoEntry) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+impl CredentialStore for KeyringStore {
+    fn get(&self, key: &str) -> Result<Option<String>, CredentialError> {
+        match keyring::Entry::new(&self.service, key)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<(), CredentialError> {
+        keyring::Entry::new(&self.service, key)?.set_password(secret)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), CredentialError> {
+        match keyring::Entry::new(&self.service, key)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn backend(&self) -> &'static str {
+        "keyring"
+    }
+}
+
+// AES-256-GCM encrypted JSON map on disk, for systems without a keyring.
+// The key sits in a separate owner-only file, which keeps secrets out of
+// plain-text config and backups of the vault alone, but not away from
+// anything running as the same user.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    // Serialises read-modify-write cycles on the vault file
+    lock: Mutex<()>,
+}
+
+impl EncryptedFileStore {
+    // Opens the vault in `dir`, generating its key on first use
+    pub fn open(dir: &Path) -> Result<Self, CredentialError> {
+        fs::create_dir_all(dir)?;
+        let key_path = dir.join(VAULT_KEY_FILE);
+
+        let key: [u8; 32] = if key_path.exists() {
+            fs::read(&key_path)?
+                .try_into()
+                .map_err(|_| CredentialError::Format(format!("{} has the wrong length", key_path.display())))?
+        } else {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&Aes256Gcm::generate_key(&mut OsRng));
+            write_private(&key_path, &key)?;
+            key
+        };
+
+        Ok(Self::with_key(dir.join(VAULT_FILE), key))
+    }
+
+    // Uses an explicit key, so the vault can be exercised without a keyring
+    // or a config dir
+    pub fn with_key(path: PathBuf, key: [u8; 32]) -> Self {
+        EncryptedFileStore {
+            path,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_all(&self) -> Result<HashMap<String, String>, CredentialError> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let data = fs::read(&self.path)?;
+        if data.len() < NONCE_LEN {
+            return Err(CredentialError::Decrypt);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CredentialError::Decrypt)?;
+        serde_json::from_slice(&plaintext).map_err(|e| CredentialError::Format(e.to_string()))
+    }
+
+    fn write_all(&self, secrets: &HashMap<String, String>) -> Result<(), CredentialError> {
+        let plaintext = serde_json::to_vec(secrets).map_err(|e| CredentialError::Format(e.to_string()))?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|e| CredentialError::Format(e.to_string()))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        let tmp = self.path.with_extension("vault.tmp");
+        write_private(&tmp, &data)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl CredentialStore for EncryptedFileStore {
+    fn get(&self, key: &str) -> Result<Option<String>, CredentialError> {
+        let _guard = self.lock.lock();
+        Ok(self.read_all()?.remove(key))
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<(), CredentialError> {
+        let _guard = self.lock.lock();
+        let mut secrets = self.read_all()?;
+        secrets.insert(key.to_string(), secret.to_string());
+        self.write_all(&secrets)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), CredentialError> {
+        let _guard = self.lock.lock();
+        let mut secrets = self.read_all()?;
+        if secrets.remove(key).is_some() {
+            self.write_all(&secrets)?;
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> &'static str {
+        "encrypted-file"
+    }
+}
+
+// The OS keyring when one is reachable, otherwise the encrypted vault in `data_dir`
+pub fn open_default(data_dir: &Path) -> Result<Arc<dyn CredentialStore>, CredentialError> {
+    let keyring = KeyringStore::new(KEYRING_SERVICE);
+    if keyring.is_available() {
+        return Ok(Arc::new(keyring));
+    }
+    eprintln!("No OS keyring available, storing credentials in an encrypted file");
+    Ok(Arc::new(EncryptedFileStore::open(data_dir)?))
+}
+
+fn write_private(path: &Path, data: &[u8]) -> Result<(), CredentialError> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(data)?;
+    }
+    #[cfg(not(unix))]
+    fs::write(path, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(dir: &Path, key: [u8; 32]) -> EncryptedFileStore {
+        EncryptedFileStore::with_key(dir.join(VAULT_FILE), key)
+    }
+
+    #[test]
+    fn round_trips_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = vault(dir.path(), [1; 32]);
+        assert_eq!(store.get("token").unwrap(), None);
+
+        store.set("token", "secret").unwrap();
+        store.set("other", "value").unwrap();
+        store.set("token", "replaced").unwrap();
+
+        // A fresh store with the same key reads what the first one wrote
+        let reopened = vault(dir.path(), [1; 32]);
+        assert_eq!(reopened.get("token").unwrap().as_deref(), Some("replaced"));
+        assert_eq!(reopened.get("other").unwrap().as_deref(), Some("value"));
+        let data = fs::read(dir.path().join(VAULT_FILE)).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("replaced"));
+        assert!(!dir.path().join("credentials.vault.tmp").exists());
+    }
+
+    #[test]
+    fn deletes_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = vault(dir.path(), [1; 32]);
+        store.set("token", "secret").unwrap();
+        store.set("other", "value").unwrap();
+
+        store.delete("token").unwrap();
+        store.delete("missing").unwrap();
+
+        assert_eq!(store.get("token").unwrap(), None);
+        assert_eq!(store.get("other").unwrap().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn refuses_a_vault_encrypted_with_another_key() {
+        let dir = tempfile::tempdir().unwrap();
+        vault(dir.path(), [1; 32]).set("token", "secret").unwrap();
+
+        let store = vault(dir.path(), [2; 32]);
+
+        assert!(matches!(store.get("token"), Err(CredentialError::Decrypt)));
+        assert!(matches!(store.set("token", "other"), Err(CredentialError::Decrypt)));
+        assert_eq!(vault(dir.path(), [1; 32]).get("token").unwrap().as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn refuses_a_corrupted_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = vault(dir.path(), [1; 32]);
+        store.set("token", "secret").unwrap();
+        let path = dir.path().join(VAULT_FILE);
+
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(store.get("token"), Err(CredentialError::Decrypt)));
+
+        // Too short to hold a nonce
+        fs::write(&path, b"short").unwrap();
+        assert!(matches!(store.get("token"), Err(CredentialError::Decrypt)));
+    }
+
+    #[test]
+    fn keeps_the_generated_key_between_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        EncryptedFileStore::open(dir.path()).unwrap().set("token", "secret").unwrap();
+
+        let reopened = EncryptedFileStore::open(dir.path()).unwrap();
+
+        assert_eq!(reopened.get("token").unwrap().as_deref(), Some("secret"));
+        assert_eq!(fs::read(dir.path().join(VAULT_KEY_FILE)).unwrap().len(), 32);
+    }
+}
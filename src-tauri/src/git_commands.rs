@@ -1,14 +1,22 @@
 use git2::{
-    Repository, PushOptions, FetchOptions, Cred, 
-    RemoteCallbacks, StashFlags, BranchType, ResetType
+    Repository, PushOptions, FetchOptions, StashFlags, BranchType, ResetType
 };
 use serde::{Serialize, Deserialize};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use notify::{Watcher, RecursiveMode, Event};
-use std::sync::mpsc::channel;
-use std::thread;
+use notify::Watcher;
+use tauri::{AppHandle, Manager};
+use crate::watcher::{self, RefsChangedEvent, RepoChangedEvent};
+use crate::status_cache::{self, RepoStatus, StatusCache};
+use parking_lot::Mutex;
+use crate::inline_diff::{self, ChangeSpan, InlineGranularity};
+use crate::syntax_highlight::{self, LineTokens, TokenSpan};
+use std::sync::Arc;
+use std::io::Read;
+use crate::search::{self, CodeSearchOptions, CodeMatch};
+use crate::remote_auth::CredentialOptions;
 
 #[derive(Debug)]
 pub enum GitError {
@@ -62,70 +70,332 @@ pub struct DiffHunk {
     pub new_lines: u32,
     pub content: String,
     pub line_type: String,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub origin: char,
+    pub old_lineno: Option<u32>,
+    pub new_lineno: Option<u32>,
+    pub content: String,
+    // Changed ranges within the line, only set on paired removed/added lines
+    pub spans: Vec<ChangeSpan>,
+    // Syntax tokens, only set when highlighting was requested
+    pub tokens: Vec<TokenSpan>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DiffStatus {
+    Unmodified,
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    Ignored,
+    // The diff viewer has always shown untracked files as "NEW"
+    #[serde(rename = "NEW")]
+    Untracked,
+    Typechange,
+    Unreadable,
+    Conflicted,
+}
+
+impl From<git2::Delta> for DiffStatus {
+    fn from(delta: git2::Delta) -> Self {
+        match delta {
+            git2::Delta::Unmodified => DiffStatus::Unmodified,
+            git2::Delta::Added => DiffStatus::Added,
+            git2::Delta::Deleted => DiffStatus::Deleted,
+            git2::Delta::Modified => DiffStatus::Modified,
+            git2::Delta::Renamed => DiffStatus::Renamed,
+            git2::Delta::Copied => DiffStatus::Copied,
+            git2::Delta::Ignored => DiffStatus::Ignored,
+            git2::Delta::Untracked => DiffStatus::Untracked,
+            git2::Delta::Typechange => DiffStatus::Typechange,
+            git2::Delta::Unreadable => DiffStatus::Unreadable,
+            git2::Delta::Conflicted => DiffStatus::Conflicted,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhitespaceMode {
+    #[default]
+    Show,
+    IgnoreAll,
+    IgnoreChange,
+    IgnoreEol,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiffViewOptions {
+    pub detect_renames: bool,
+    pub rename_threshold: u16,
+    pub detect_copies: bool,
+    pub copy_threshold: u16,
+    pub whitespace: WhitespaceMode,
+    pub context_lines: u32,
+    pub pathspecs: Vec<String>,
+    // Hunk content per file is cut off after this many bytes
+    pub max_file_bytes: usize,
+    pub inline_granularity: InlineGranularity,
+    pub highlight: bool,
+}
+
+impl Default for DiffViewOptions {
+    fn default() -> Self {
+        // Same defaults as `git diff -M`
+        DiffViewOptions {
+            detect_renames: true,
+            rename_threshold: 50,
+            detect_copies: false,
+            copy_threshold: 50,
+            whitespace: WhitespaceMode::Show,
+            context_lines: 3,
+            pathspecs: Vec::new(),
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+            inline_granularity: InlineGranularity::Word,
+            highlight: false,
+        }
+    }
+}
+
+impl DiffViewOptions {
+    fn diff_options(&self) -> git2::DiffOptions {
+        let mut opts = git2::DiffOptions::new();
+        opts.context_lines(self.context_lines);
+        // Anything bigger is reported as binary instead of being loaded
+        opts.max_size(MAX_DIFF_FILE_BYTES);
+        match self.whitespace {
+            WhitespaceMode::Show => {}
+            WhitespaceMode::IgnoreAll => { opts.ignore_whitespace(true); }
+            WhitespaceMode::IgnoreChange => { opts.ignore_whitespace_change(true); }
+            WhitespaceMode::IgnoreEol => { opts.ignore_whitespace_eol(true); }
+        }
+        for pathspec in &self.pathspecs {
+            opts.pathspec(pathspec);
+        }
+        opts
+    }
+
+    fn find_similar(&self, diff: &mut git2::Diff) -> Result<(), GitError> {
+        if !self.detect_renames && !self.detect_copies {
+            return Ok(());
+        }
+
+        let mut find_opts = git2::DiffFindOptions::new();
+        find_opts.renames(self.detect_renames);
+        find_opts.rename_threshold(self.rename_threshold);
+        find_opts.copies(self.detect_copies);
+        find_opts.copy_threshold(self.copy_threshold);
+        // A file moved in the working directory shows up as untracked
+        find_opts.for_untracked(true);
+        if self.whitespace != WhitespaceMode::Show {
+            find_opts.ignore_whitespace(true);
+        }
+        diff.find_similar(Some(&mut find_opts))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub format: String,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Both sides of a binary change written to disk so the frontend can load
+// them through the asset protocol
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BinaryDiffAssets {
+    pub old_file: Option<String>,
+    pub new_file: Option<String>,
+    pub old_image: Option<ImageInfo>,
+    pub new_image: Option<ImageInfo>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DiffEntry {
     pub old_path: Option<String>,
     pub new_path: Option<String>,
-    pub status: String,
+    pub status: DiffStatus,
+    // Percentage similarity for renamed and copied files
+    pub similarity: Option<u16>,
+    pub is_binary: bool,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub old_id: Option<String>,
+    pub new_id: Option<String>,
+    // Format and dimensions, only filled in for binary files that are images
+    pub old_image: Option<ImageInfo>,
+    pub new_image: Option<ImageInfo>,
+    pub truncated: bool,
+    pub additions: usize,
+    pub deletions: usize,
     pub hunks: Vec<DiffHunk>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlameHunk {
+    pub commit_id: String,
+    pub author: String,
+    pub author_email: String,
+    pub date: i64,
+    pub summary: String,
+    pub final_start_line: usize,
+    pub orig_start_line: usize,
+    pub lines: usize,
+    pub orig_path: Option<String>,
+    pub is_boundary: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BlameFileOptions {
+    pub ignore_whitespace: bool,
+    pub follow_moves: bool,
+    pub follow_copies: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileHistoryEntry {
+    pub commit_id: String,
+    pub author: String,
+    pub date: i64,
+    pub summary: String,
+    pub path: String,
+    pub old_path: Option<String>,
+    pub status: DiffStatus,
+    pub patch: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TreeEntryInfo {
+    pub name: String,
+    pub path: String,
+    pub id: String,
+    pub kind: String,
+    pub mode: i32,
+    pub size: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileContent {
+    pub path: String,
+    pub id: String,
+    pub size: usize,
+    pub is_binary: bool,
+    pub encoding: Option<String>,
+    pub truncated: bool,
+    pub content: Option<String>,
+    pub tokens: Option<Vec<Vec<TokenSpan>>>,
+}
+
+// Blobs larger than this are truncated unless the caller asks for more
+pub const DEFAULT_MAX_FILE_BYTES: usize = 1024 * 1024;
+
+// Enough of a file to recognise any supported image format
+const IMAGE_HEADER_BYTES: usize = 64 * 1024;
+
+// Files above this size are never loaded for diffing
+const MAX_DIFF_FILE_BYTES: i64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchTarget {
+    Workdir,
+    Index,
+    Both,
+}
+
+impl From<PatchTarget> for git2::ApplyLocation {
+    fn from(target: PatchTarget) -> Self {
+        match target {
+            PatchTarget::Workdir => git2::ApplyLocation::WorkDir,
+            PatchTarget::Index => git2::ApplyLocation::Index,
+            PatchTarget::Both => git2::ApplyLocation::Both,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HunkCheck {
+    pub file: Option<String>,
+    pub header: String,
+    pub old_start: u32,
+    pub new_start: u32,
+    pub applies: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatchCheckReport {
+    pub applies_cleanly: bool,
+    pub hunks: Vec<HunkCheck>,
+}
+
 pub struct GitRepo {
     repo: Repository,
     path: PathBuf,
     watcher: Option<Box<dyn Watcher + Send>>,
+    status_cache: Arc<Mutex<StatusCache>>,
 }
 
 impl GitRepo {
     pub fn open(path: &str, _token: Option<String>) -> Result<Self, GitError> {
         let repo = Repository::open(path)?;
-        let mut git_repo = GitRepo {
+        Ok(GitRepo {
             repo,
             path: PathBuf::from(path),
             watcher: None,
-        };
-        git_repo.setup_watcher()?;
-        Ok(git_repo)
+            status_cache: Arc::default(),
+        })
     }
 
-    fn setup_watcher(&mut self) -> Result<(), GitError> {
-        let (tx, rx) = channel();
-        
-        // Create a new watcher
-        let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
-            if let Ok(event) = res {
-                let _ = tx.send(event);
+    // Starts watching the working tree and pushes debounced changes to the
+    // frontend: `repo-changed` for files, `refs-changed` for HEAD/refs/index
+    pub fn watch(&mut self, app_handle: AppHandle) -> Result<(), GitError> {
+        let repo_path = self.path.to_string_lossy().into_owned();
+        // The watcher thread keeps the status cache current with its own handle
+        let status_repo = Repository::open(&self.path)?;
+        let status_cache = self.status_cache.clone();
+
+        let watcher = watcher::watch_repository(&self.path, move |batch| {
+            // Refresh before notifying so a `get_status` issued in response
+            // to the event already sees the change
+            if let Err(e) = status_cache::refresh(&status_cache, &status_repo, &batch) {
+                eprintln!("Failed to refresh status cache: {}", e);
             }
-        }).map_err(|e| GitError::Custom(e.to_string()))?;
-
-        // Watch the repository path
-        watcher.watch(&self.path, RecursiveMode::Recursive)
-            .map_err(|e| GitError::Custom(e.to_string()))?;
-        // Store the watcher
-        self.watcher = Some(Box::new(watcher));
-
-        // Spawn a thread to handle file system events
-        let _path = self.path.clone();
-        thread::spawn(move || {
-            while let Ok(event) = rx.recv() {
-                match event.kind {
-                    notify::EventKind::Create(_) |
-                    notify::EventKind::Modify(_) |
-                    notify::EventKind::Remove(_) => {
-                        // You could emit an event here to notify the frontend
-                        println!("File system event: {:?}", event);
-                    }
-                    _ => {}
-                }
+            // New ignore rules change the status without touching any file in it
+            if !batch.worktree.is_empty() || batch.ignore_rules_changed {
+                let _ = app_handle.emit_all("repo-changed", RepoChangedEvent {
+                    repo_path: repo_path.clone(),
+                    paths: batch.worktree,
+                });
+            }
+            if !batch.git.is_empty() {
+                let _ = app_handle.emit_all("refs-changed", RefsChangedEvent {
+                    repo_path: repo_path.clone(),
+                    paths: batch.git,
+                });
             }
-        });
+        })?;
 
+        self.watcher = Some(watcher);
         Ok(())
     }
 
-    pub fn clone(url: &str, path: &Path) -> Result<Self, GitError> {
+    // Staged/unstaged/untracked/ignored/conflicted buckets, served from the
+    // cache that the watcher keeps up to date
+    pub fn status(&self) -> Result<RepoStatus, GitError> {
+        status_cache::snapshot(&self.status_cache, &self.repo)
+    }
+
+    pub fn clone(url: &str, path: &Path, credentials: CredentialOptions) -> Result<Self, GitError> {
         // Ensure parent directory exists with proper permissions
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)
@@ -141,16 +411,9 @@ impl GitRepo {
             }
         }
 
-        let token = std::env::var("GITHUB_TOKEN")
-            .map_err(|_| GitError::Custom("GitHub token not found".to_string()))?;
-
-        let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
-            Cred::userpass_plaintext("oauth2", &token)
-        });
-
+        let verification = credentials.verification.clone();
         let mut fetch_options = FetchOptions::new();
-        fetch_options.remote_callbacks(callbacks);
+        fetch_options.remote_callbacks(credentials.remote_callbacks(url));
 
         let mut builder = git2::build::RepoBuilder::new();
         builder.fetch_options(fetch_options);
@@ -160,27 +423,27 @@ impl GitRepo {
                 repo,
                 path: path.to_path_buf(),
                 watcher: None,
+                status_cache: Arc::default(),
             }),
             Err(e) => {
                 // Clean up failed clone attempt
                 if path.exists() {
                     let _ = std::fs::remove_dir_all(path);
                 }
-                Err(GitError::Git(e))
+                Err(verification.explain(e))
             }
         }
     }
 
-    pub fn push(&self, _is_remote: bool) -> Result<(), GitError> {
+    // `credentials` carries the token of the account bound to origin and
+    // the SSH keys to try
+    pub fn push(&self, _is_remote: bool, credentials: CredentialOptions) -> Result<(), GitError> {
         let mut remote = self.repo.find_remote("origin")?;
-        let mut callbacks = RemoteCallbacks::new();
-        
-        callbacks.credentials(|_url, username_from_url, _allowed_types| {
-            Cred::ssh_key_from_agent(username_from_url.unwrap_or("git"))
-        });
 
+        let verification = credentials.verification.clone();
+        let url = remote.pushurl().or(remote.url()).unwrap_or_default().to_string();
         let mut push_options = PushOptions::new();
-        push_options.remote_callbacks(callbacks);
+        push_options.remote_callbacks(credentials.remote_callbacks(&url));
 
         let head = self.repo.head()?;
         let branch_name = head.shorthand().ok_or(GitError::Custom("Cannot get branch name".into()))?;
@@ -188,23 +451,21 @@ impl GitRepo {
         remote.push(
             &[&format!("refs/heads/{}", branch_name)],
             Some(&mut push_options),
-        )?;
+        ).map_err(|e| verification.explain(e))?;
 
         Ok(())
     }
 
-    pub fn pull(&self, _is_remote: bool) -> Result<(), GitError> {
+    pub fn pull(&self, _is_remote: bool, credentials: CredentialOptions) -> Result<(), GitError> {
         let mut remote = self.repo.find_remote("origin")?;
-        let mut callbacks = RemoteCallbacks::new();
-        
-        callbacks.credentials(|_url, username_from_url, _allowed_types| {
-            Cred::ssh_key_from_agent(username_from_url.unwrap_or("git"))
-        });
 
+        let verification = credentials.verification.clone();
+        let url = remote.url().unwrap_or_default().to_string();
         let mut fetch_options = FetchOptions::new();
-        fetch_options.remote_callbacks(callbacks);
+        fetch_options.remote_callbacks(credentials.remote_callbacks(&url));
 
-        remote.fetch(&[] as &[&str], Some(&mut fetch_options), None)?;
+        remote.fetch(&[] as &[&str], Some(&mut fetch_options), None)
+            .map_err(|e| verification.explain(e))?;
 
         let head = self.repo.head()?;
         let branch_name = head.shorthand().ok_or(GitError::Custom("Cannot get branch name".into()))?;
@@ -304,87 +565,284 @@ impl GitRepo {
         Ok(())
     }
 
-    pub fn view_diff(&self) -> Result<Vec<DiffEntry>, GitError> {
-        let mut diff_entries = Vec::new();
-        let mut opts = git2::DiffOptions::new();
+    pub fn view_diff(&self, options: &DiffViewOptions) -> Result<Vec<DiffEntry>, GitError> {
+        let mut opts = options.diff_options();
+
+        // Let libgit2 read untracked files so binary detection and the
+        // size limits apply to them as well
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
         
         // Get the diff between the index and working directory
-        let diff = self.repo.diff_index_to_workdir(None, Some(&mut opts))?;
-        
-        let current_entry = std::cell::RefCell::new(None::<DiffEntry>);
+        let mut diff = self.repo.diff_index_to_workdir(None, Some(&mut opts))?;
+        options.find_similar(&mut diff)?;
         
-        // Process regular diffs
-        diff.foreach(
-            &mut |delta, _| {
-                if let Some(entry) = current_entry.borrow_mut().take() {
-                    diff_entries.push(entry);
+        collect_diff_entries(&self.repo, &diff, options)
+    }
+
+    // Writes the old and new versions of a binary file into `dest_dir`. The new
+    // side falls back to the working directory copy when it isn't a stored blob.
+    pub fn export_binary_versions(
+        &self,
+        old_id: Option<&str>,
+        new_id: Option<&str>,
+        file_path: &str,
+        dest_dir: &Path,
+    ) -> Result<BinaryDiffAssets, GitError> {
+        std::fs::create_dir_all(dest_dir)?;
+        let extension = Path::new(file_path).extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| format!(".{}", ext))
+            .unwrap_or_default();
+
+        let write_version = |data: &[u8], id: git2::Oid| -> Result<(String, Option<ImageInfo>), GitError> {
+            // Named by content hash so unchanged versions are only written once
+            let target = dest_dir.join(format!("{}{}", id, extension));
+            if !target.exists() {
+                std::fs::write(&target, data)?;
+            }
+            Ok((target.to_string_lossy().into_owned(), image_info(data)))
+        };
+
+        let (old_file, old_image) = match old_id {
+            Some(id) => {
+                let blob = self.repo.find_blob(git2::Oid::from_str(id)?)?;
+                let (file, image) = write_version(blob.content(), blob.id())?;
+                (Some(file), image)
+            }
+            None => (None, None),
+        };
+
+        let stored_new = new_id
+            .and_then(|id| git2::Oid::from_str(id).ok())
+            .and_then(|id| self.repo.find_blob(id).ok());
+        let (new_file, new_image) = match stored_new {
+            Some(blob) => {
+                let (file, image) = write_version(blob.content(), blob.id())?;
+                (Some(file), image)
+            }
+            None => {
+                let full_path = self.workdir_path(file_path)?;
+                if full_path.is_file() {
+                    let data = std::fs::read(&full_path)?;
+                    let id = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+                    let (file, image) = write_version(&data, id)?;
+                    (Some(file), image)
+                } else {
+                    (None, None)
                 }
-                
-                *current_entry.borrow_mut() = Some(DiffEntry {
-                    old_path: delta.old_file().path().map(|p| p.to_string_lossy().into_owned()),
-                    new_path: delta.new_file().path().map(|p| p.to_string_lossy().into_owned()),
-                    status: format!("{:?}", delta.status()),
-                    hunks: Vec::new(),
-                });
-                true
-            },
-            None,
-            Some(&mut |_delta, hunk| {
-                if let Some(entry) = &mut *current_entry.borrow_mut() {
-                    entry.hunks.push(DiffHunk {
+            }
+        };
+
+        Ok(BinaryDiffAssets { old_file, new_file, old_image, new_image })
+    }
+
+    // `file_path` resolved inside the working directory. Absolute paths, `..`
+    // and symlinks that lead out of it are refused.
+    fn workdir_path(&self, file_path: &str) -> Result<PathBuf, GitError> {
+        let relative = Path::new(file_path);
+        let is_relative = relative.components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_relative {
+            return Err(GitError::Custom(format!("Not a path inside the repository: {}", file_path)));
+        }
+        let full_path = self.path.join(relative);
+        match std::fs::canonicalize(&full_path) {
+            Ok(resolved) if resolved.starts_with(std::fs::canonicalize(&self.path)?) => Ok(resolved),
+            Ok(_) => Err(GitError::Custom(format!("{} points outside the repository", file_path))),
+            // Deleted in the working directory
+            Err(_) => Ok(full_path),
+        }
+    }
+
+    // Writes one mbox-style file per commit, like `git format-patch`
+    pub fn export_patches(&self, range: &str, dest_dir: &Path) -> Result<Vec<String>, GitError> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+        // Either side of a range defaults to HEAD, as in git
+        let commit_id = |rev: &str| -> Result<git2::Oid, GitError> {
+            let rev = if rev.is_empty() { "HEAD" } else { rev };
+            Ok(self.repo.revparse_single(rev)?.peel_to_commit()?.id())
+        };
+        if let Some((left, right)) = range.split_once("...") {
+            // Commits on either side but not on both
+            let (left, right) = (commit_id(left)?, commit_id(right)?);
+            revwalk.push(left)?;
+            revwalk.push(right)?;
+            revwalk.hide(self.repo.merge_base(left, right)?)?;
+        } else if let Some((from, to)) = range.split_once("..") {
+            revwalk.push(commit_id(to)?)?;
+            revwalk.hide(commit_id(from)?)?;
+        } else {
+            let commit = self.repo.revparse_single(range)?.peel_to_commit()?;
+            revwalk.push(commit.id())?;
+            for parent in commit.parent_ids() {
+                revwalk.hide(parent)?;
+            }
+        }
+
+        // Merge commits have no single patch, format-patch skips them too
+        let mut commits = Vec::new();
+        for commit_id in revwalk {
+            let commit = self.repo.find_commit(commit_id?)?;
+            if commit.parent_count() <= 1 {
+                commits.push(commit);
+            }
+        }
+
+        std::fs::create_dir_all(dest_dir)?;
+        let mut files = Vec::with_capacity(commits.len());
+        for (idx, commit) in commits.iter().enumerate() {
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let diff = self.repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+
+            let summary = commit.summary().unwrap_or("");
+            let body = commit.body().unwrap_or("");
+            let mut opts = git2::EmailCreateOptions::new();
+            let email = git2::Email::from_diff(
+                &diff,
+                idx + 1,
+                commits.len(),
+                &commit.id(),
+                summary,
+                body,
+                &commit.author(),
+                &mut opts,
+            )?;
+
+            let file = dest_dir.join(format!("{:04}-{}.patch", idx + 1, patch_file_slug(summary)));
+            std::fs::write(&file, email.as_slice())?;
+            files.push(file.to_string_lossy().into_owned());
+        }
+
+        Ok(files)
+    }
+
+    // Unified diff of HEAD against the working tree, staged changes included
+    pub fn export_working_diff(&self, destination: &Path) -> Result<(), GitError> {
+        let head_tree = self.repo.head()?.peel_to_tree()?;
+        let diff = self.repo.diff_tree_to_workdir_with_index(Some(&head_tree), None)?;
+
+        let mut patch = Vec::new();
+        diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                patch.push(line.origin() as u8);
+            }
+            patch.extend_from_slice(line.content());
+            true
+        })?;
+
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(destination, patch)?;
+        Ok(())
+    }
+
+    pub fn apply_patch(&self, patch_file: &Path, target: PatchTarget) -> Result<(), GitError> {
+        let diff = git2::Diff::from_buffer(&std::fs::read(patch_file)?)?;
+        self.repo.apply(&diff, target.into(), None)?;
+        Ok(())
+    }
+
+    // Dry run: tries every hunk on its own so the report shows exactly which
+    // ones would fail instead of stopping at the first conflict
+    pub fn check_patch(&self, patch_file: &Path, target: PatchTarget) -> Result<PatchCheckReport, GitError> {
+        let diff = git2::Diff::from_buffer(&std::fs::read(patch_file)?)?;
+
+        let mut hunks = Vec::new();
+        // Delta and hunk within it, for each entry of `hunks`
+        let mut positions = Vec::new();
+        for (delta_idx, delta) in diff.deltas().enumerate() {
+            let file = delta.new_file().path()
+                .or_else(|| delta.old_file().path())
+                .map(|p| p.to_string_lossy().into_owned());
+            if let Some(patch) = git2::Patch::from_diff(&diff, delta_idx)? {
+                for hunk_idx in 0..patch.num_hunks() {
+                    let (hunk, _) = patch.hunk(hunk_idx)?;
+                    hunks.push(HunkCheck {
+                        file: file.clone(),
+                        header: String::from_utf8_lossy(hunk.header()).trim_end().to_string(),
                         old_start: hunk.old_start(),
                         new_start: hunk.new_start(),
-                        old_lines: hunk.old_lines(),
-                        new_lines: hunk.new_lines(),
-                        content: String::new(),
-                        line_type: "header".to_string(),
+                        applies: false,
                     });
+                    positions.push((delta_idx, hunk_idx));
                 }
-                true
-            }),
-            Some(&mut |_delta, _hunk, line| {
-                if let Some(entry) = &mut *current_entry.borrow_mut() {
-                    if let Some(last_hunk) = entry.hunks.last_mut() {
-                        let content = String::from_utf8_lossy(line.content());
-                        last_hunk.content.push_str(&format!("{}{}\n", 
-                            line.origin() as char,
-                            content.trim_end()
-                        ));
-                    }
-                }
-                true
-            }),
-        )?;
+            }
+        }
 
-        if let Some(entry) = current_entry.into_inner() {
-            diff_entries.push(entry);
+        // Other files are skipped entirely, so one whose preimage is missing
+        // can't fail the check for the rest. Callbacks arrive in the same
+        // order the deltas and hunks were listed above.
+        for (hunk, &(target_delta, target_hunk)) in hunks.iter_mut().zip(&positions) {
+            let (mut delta_idx, mut hunk_idx) = (0, 0);
+            let mut opts = git2::ApplyOptions::new();
+            opts.check(true);
+            opts.delta_callback(|_delta| {
+                let apply = delta_idx == target_delta;
+                delta_idx += 1;
+                apply
+            });
+            opts.hunk_callback(|_hunk| {
+                let apply = hunk_idx == target_hunk;
+                hunk_idx += 1;
+                apply
+            });
+            hunk.applies = self.repo.apply(&diff, target.into(), Some(&mut opts)).is_ok();
         }
 
-        // Add untracked files
-        let untracked = self.get_untracked_files()?;
-        for path in untracked {
-            // Read the content of the new file
-            let content = std::fs::read_to_string(self.path.join(&path))
-                .map_err(GitError::Io)?;
+        let applies_cleanly = {
+            let mut opts = git2::ApplyOptions::new();
+            opts.check(true);
+            self.repo.apply(&diff, target.into(), Some(&mut opts)).is_ok()
+        };
 
-            let hunk = DiffHunk {
-                old_start: 0,
-                new_start: 1,
-                old_lines: 0,
-                new_lines: content.lines().count() as u32,
-                content: content.lines().map(|line| format!("+{}\n", line)).collect(),
-                line_type: "header".to_string(),
-            };
+        Ok(PatchCheckReport { applies_cleanly, hunks })
+    }
 
-            diff_entries.push(DiffEntry {
-                old_path: None,
-                new_path: Some(path),
-                status: "NEW".to_string(),
-                hunks: vec![hunk],
-            });
+    // Accepts `A B`, `A..B`, `A...B` (diff against the merge base) or a single
+    // commit, which is compared against its first parent
+    pub fn diff_revisions(&self, from: &str, to: Option<&str>, options: &DiffViewOptions) -> Result<Vec<DiffEntry>, GitError> {
+        let (old_tree, new_tree) = self.resolve_diff_trees(from, to)?;
+        let mut opts = options.diff_options();
+        let mut diff = self.repo.diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), Some(&mut opts))?;
+        options.find_similar(&mut diff)?;
+        collect_diff_entries(&self.repo, &diff, options)
+    }
+
+    fn resolve_diff_trees(&self, from: &str, to: Option<&str>) -> Result<(Option<git2::Tree<'_>>, git2::Tree<'_>), GitError> {
+        let commit_at = |rev: &str| -> Result<git2::Commit<'_>, GitError> {
+            let rev = if rev.is_empty() { "HEAD" } else { rev };
+            Ok(self.repo.revparse_single(rev)?.peel_to_commit()?)
+        };
+
+        if let Some(to) = to {
+            return Ok((Some(commit_at(from)?.tree()?), commit_at(to)?.tree()?));
+        }
+
+        if let Some((left, right)) = from.split_once("...") {
+            let left = commit_at(left)?;
+            let right = commit_at(right)?;
+            let base = self.repo.merge_base(left.id(), right.id())?;
+            return Ok((Some(self.repo.find_commit(base)?.tree()?), right.tree()?));
         }
 
-        Ok(diff_entries)
+        if let Some((left, right)) = from.split_once("..") {
+            return Ok((Some(commit_at(left)?.tree()?), commit_at(right)?.tree()?));
+        }
+
+        let commit = commit_at(from)?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            // Root commits are diffed against the empty tree
+            Err(_) => None,
+        };
+        Ok((parent_tree, commit.tree()?))
     }
 
     pub fn create_tag(&self, tag_name: &str, message: &str) -> Result<(), GitError> {
@@ -569,6 +1027,265 @@ impl GitRepo {
         Ok(tags)
     }
 
+    pub fn blame_file(&self, file_path: &str, rev: Option<&str>, options: &BlameFileOptions) -> Result<Vec<BlameHunk>, GitError> {
+        let mut opts = git2::BlameOptions::new();
+        opts.ignore_whitespace(options.ignore_whitespace);
+
+        // Moves/copies within the same commit are the cheap cases; copies from
+        // any commit need a much wider search, so only enable it on request
+        if options.follow_moves || options.follow_copies {
+            opts.track_copies_same_file(true);
+            opts.track_copies_same_commit_moves(true);
+        }
+        if options.follow_copies {
+            opts.track_copies_same_commit_copies(true);
+            opts.track_copies_any_commit_copies(true);
+        }
+
+        if let Some(rev) = rev {
+            let commit = self.repo.revparse_single(rev)?.peel_to_commit()?;
+            opts.newest_commit(commit.id());
+        }
+
+        let blame = self.repo.blame_file(Path::new(file_path), Some(&mut opts))?;
+
+        let mut hunks = Vec::with_capacity(blame.len());
+        for hunk in blame.iter() {
+            let commit_id = hunk.final_commit_id();
+            let signature = hunk.final_signature();
+
+            // Boundary hunks can point at commits outside a shallow history
+            let summary = self.repo.find_commit(commit_id)
+                .ok()
+                .and_then(|commit| commit.summary().map(String::from))
+                .unwrap_or_default();
+
+            hunks.push(BlameHunk {
+                commit_id: commit_id.to_string(),
+                author: signature.name().unwrap_or("Unknown").to_string(),
+                author_email: signature.email().unwrap_or("").to_string(),
+                date: signature.when().seconds(),
+                summary,
+                final_start_line: hunk.final_start_line(),
+                orig_start_line: hunk.orig_start_line(),
+                lines: hunk.lines_in_hunk(),
+                orig_path: hunk.path().map(|p| p.to_string_lossy().into_owned()),
+                is_boundary: hunk.is_boundary(),
+            });
+        }
+
+        Ok(hunks)
+    }
+
+    pub fn file_history(&self, file_path: &str, rev: Option<&str>, max_count: Option<usize>) -> Result<Vec<FileHistoryEntry>, GitError> {
+        let start = match rev {
+            Some(rev) => self.repo.revparse_single(rev)?.peel_to_commit()?.id(),
+            None => self.repo.head()?.peel_to_commit()?.id(),
+        };
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push(start)?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        // The file's name in each commit still to be visited. Topological
+        // order visits every child before its parents, so each commit has
+        // its name by the time it comes up; merges pass a name to each
+        // parent, which differ when one side renamed the file.
+        let mut paths = HashMap::from([(start, PathBuf::from(file_path))]);
+        let mut history = Vec::new();
+
+        for commit_id in revwalk {
+            if max_count.map_or(false, |max| history.len() >= max) {
+                break;
+            }
+
+            let commit_id = commit_id?;
+            let current_path = match paths.remove(&commit_id) {
+                Some(path) => path,
+                // Only reachable through parents that history simplification left out
+                None => continue,
+            };
+            let commit = self.repo.find_commit(commit_id)?;
+            let parents: Vec<_> = commit.parents().collect();
+            let mut follow = |parent: &git2::Commit, path: &Path| {
+                paths.entry(parent.id()).or_insert_with(|| path.to_path_buf());
+            };
+
+            let tree = commit.tree()?;
+            let entry_id = match tree.get_path(&current_path) {
+                Ok(entry) => entry.id(),
+                // The file doesn't exist at this commit under this name
+                Err(_) => {
+                    parents.iter().for_each(|parent| follow(parent, &current_path));
+                    continue;
+                }
+            };
+
+            // Skip commits where the file is unchanged relative to a parent,
+            // and follow only that parent, like git log does
+            let unchanged = parents.iter().find(|parent| {
+                parent.tree().ok()
+                    .and_then(|tree| tree.get_path(&current_path).ok())
+                    .map_or(false, |entry| entry.id() == entry_id)
+            });
+            if let Some(parent) = unchanged {
+                follow(parent, &current_path);
+                continue;
+            }
+
+            let parent_tree = match parents.first() {
+                Some(parent) => Some(parent.tree()?),
+                None => None,
+            };
+
+            // Diff the whole tree so rename detection can see the file's old name
+            let diff = renames_diff(&self.repo, parent_tree.as_ref(), &tree)?;
+            let delta_idx = diff.deltas()
+                .position(|delta| delta.new_file().path() == Some(current_path.as_path()));
+            let delta_idx = match delta_idx {
+                Some(idx) => idx,
+                None => continue,
+            };
+
+            let delta = diff.get_delta(delta_idx)
+                .ok_or_else(|| GitError::Custom("Missing diff delta".into()))?;
+            let old_path = delta.old_file().path()
+                .filter(|_| delta.status() == git2::Delta::Renamed)
+                .map(Path::to_path_buf);
+            let status = delta.status();
+
+            let patch = match git2::Patch::from_diff(&diff, delta_idx)? {
+                Some(mut patch) => String::from_utf8_lossy(&patch.to_buf()?).into_owned(),
+                None => String::new(),
+            };
+
+            // Keep following the file under the name each parent has for it
+            for (index, parent) in parents.iter().enumerate() {
+                let parent_path = if index == 0 {
+                    old_path.clone()
+                } else {
+                    renamed_from(&self.repo, parent, &tree, &current_path)?
+                };
+                follow(parent, parent_path.as_deref().unwrap_or(&current_path));
+            }
+
+            let author = commit.author();
+            history.push(FileHistoryEntry {
+                commit_id: commit.id().to_string(),
+                author: author.name().unwrap_or("Unknown").to_string(),
+                date: author.when().seconds(),
+                summary: commit.summary().unwrap_or("").to_string(),
+                path: current_path.to_string_lossy().into_owned(),
+                old_path: old_path.map(|p| p.to_string_lossy().into_owned()),
+                status: status.into(),
+                patch,
+            });
+        }
+
+        Ok(history)
+    }
+
+    fn tree_at(&self, rev: &str) -> Result<git2::Tree<'_>, GitError> {
+        Ok(self.repo.revparse_single(rev)?.peel_to_tree()?)
+    }
+
+    fn blob_at(&self, rev: &str, file_path: &str) -> Result<git2::Blob<'_>, GitError> {
+        let tree = self.tree_at(rev)?;
+        let entry = tree.get_path(Path::new(file_path))?;
+        let blob = entry.to_object(&self.repo)?
+            .into_blob()
+            .map_err(|_| GitError::Custom(format!("'{}' is not a file at {}", file_path, rev)))?;
+        Ok(blob)
+    }
+
+    pub fn list_tree(&self, rev: &str, dir: Option<&str>) -> Result<Vec<TreeEntryInfo>, GitError> {
+        let root = self.tree_at(rev)?;
+        let prefix = dir.map(|d| d.trim_matches('/')).filter(|d| !d.is_empty());
+
+        let tree = match prefix {
+            Some(dir) => root.get_path(Path::new(dir))?
+                .to_object(&self.repo)?
+                .into_tree()
+                .map_err(|_| GitError::Custom(format!("'{}' is not a directory at {}", dir, rev)))?,
+            None => root,
+        };
+
+        // Sizes come from the object headers, which spares loading every blob
+        let odb = self.repo.odb()?;
+        let mut entries = Vec::with_capacity(tree.len());
+        for entry in tree.iter() {
+            let name = entry.name().map(String::from)
+                .unwrap_or_else(|| String::from_utf8_lossy(entry.name_bytes()).into_owned());
+            let path = match prefix {
+                Some(dir) => format!("{}/{}", dir, name),
+                None => name.clone(),
+            };
+            let size = match entry.kind() {
+                Some(git2::ObjectType::Blob) => odb.read_header(entry.id()).ok().map(|(size, _)| size),
+                _ => None,
+            };
+
+            entries.push(TreeEntryInfo {
+                name,
+                path,
+                id: entry.id().to_string(),
+                kind: entry.kind().map(|k| k.str().to_string()).unwrap_or_else(|| "unknown".into()),
+                mode: entry.filemode(),
+                size,
+            });
+        }
+
+        // Directories first, then files, both alphabetically
+        entries.sort_by(|a, b| (a.kind != "tree").cmp(&(b.kind != "tree")).then_with(|| a.name.cmp(&b.name)));
+        Ok(entries)
+    }
+
+    pub fn read_file_at(&self, rev: &str, file_path: &str, max_bytes: Option<usize>, highlight: bool) -> Result<FileContent, GitError> {
+        let blob = self.blob_at(rev, file_path)?;
+        let data = blob.content();
+        let max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES);
+
+        let encoding = detect_encoding(data);
+        let is_binary = encoding.is_none();
+        // `max_bytes` applies to the text after the byte order mark
+        let text = &data[encoding.map_or(0, bom_len)..];
+        let truncated = !is_binary && text.len() > max_bytes;
+
+        let content = encoding.map(|encoding| decode_text(&text[..text.len().min(max_bytes)], encoding));
+
+        // Token offsets are only meaningful when the content is returned as-is
+        let tokens = match &content {
+            Some(text) if highlight && encoding == Some("utf-8") && data.len() <= DEFAULT_MAX_FILE_BYTES => {
+                syntax_highlight::highlight_blob(blob.id(), Path::new(file_path), data)
+                    .map(|tokens| tokens.iter().take(text.lines().count()).cloned().collect())
+            }
+            _ => None,
+        };
+
+        Ok(FileContent {
+            path: file_path.to_string(),
+            id: blob.id().to_string(),
+            size: blob.size(),
+            is_binary,
+            encoding: encoding.map(String::from),
+            truncated,
+            content,
+            tokens,
+        })
+    }
+
+    pub fn save_file_at(&self, rev: &str, file_path: &str, destination: &Path) -> Result<(), GitError> {
+        let blob = self.blob_at(rev, file_path)?;
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(destination, blob.content())?;
+        Ok(())
+    }
+
+    pub fn search_code(&self, rev: &str, options: &CodeSearchOptions) -> Result<Vec<CodeMatch>, GitError> {
+        search::search_code(&self.repo, rev, options)
+    }
+
     pub fn get_path(&self) -> PathBuf {
         self.path.clone()
     }
@@ -579,4 +1296,514 @@ impl Drop for GitRepo {
         // Clean up the watcher when the GitRepo is dropped
         self.watcher = None;
     }
-}
\ No newline at end of file
+}
+
+fn collect_diff_entries(repo: &Repository, diff: &git2::Diff, options: &DiffViewOptions) -> Result<Vec<DiffEntry>, GitError> {
+    let max_bytes = options.max_file_bytes;
+    let mut diff_entries = Vec::new();
+    let current_entry = std::cell::RefCell::new(None::<DiffEntry>);
+    let content_bytes = std::cell::Cell::new(0usize);
+
+    diff.foreach(
+        &mut |delta, _| {
+            if let Some(entry) = current_entry.borrow_mut().take() {
+                diff_entries.push(entry);
+            }
+            content_bytes.set(0);
+
+            let (old_file, new_file) = (delta.old_file(), delta.new_file());
+            *current_entry.borrow_mut() = Some(DiffEntry {
+                old_path: old_file.path().map(|p| p.to_string_lossy().into_owned()),
+                new_path: new_file.path().map(|p| p.to_string_lossy().into_owned()),
+                status: delta.status().into(),
+                similarity: None,
+                is_binary: delta.flags().is_binary() || old_file.is_binary() || new_file.is_binary(),
+                old_size: old_file.size(),
+                new_size: new_file.size(),
+                old_id: Some(old_file.id()).filter(|id| !id.is_zero()).map(|id| id.to_string()),
+                new_id: Some(new_file.id()).filter(|id| !id.is_zero()).map(|id| id.to_string()),
+                old_image: None,
+                new_image: None,
+                truncated: false,
+                additions: 0,
+                deletions: 0,
+                hunks: Vec::new(),
+            });
+            true
+        },
+        Some(&mut |_delta, _binary| {
+            if let Some(entry) = &mut *current_entry.borrow_mut() {
+                entry.is_binary = true;
+            }
+            true
+        }),
+        Some(&mut |_delta, hunk| {
+            if let Some(entry) = &mut *current_entry.borrow_mut() {
+                if entry.truncated {
+                    return true;
+                }
+                entry.hunks.push(DiffHunk {
+                    old_start: hunk.old_start(),
+                    new_start: hunk.new_start(),
+                    old_lines: hunk.old_lines(),
+                    new_lines: hunk.new_lines(),
+                    content: String::new(),
+                    line_type: "header".to_string(),
+                    lines: Vec::new(),
+                });
+            }
+            true
+        }),
+        Some(&mut |_delta, _hunk, line| {
+            if let Some(entry) = &mut *current_entry.borrow_mut() {
+                match line.origin() {
+                    '+' => entry.additions += 1,
+                    '-' => entry.deletions += 1,
+                    _ => {}
+                }
+
+                // Keep counting lines for the stats but stop collecting content
+                if entry.truncated {
+                    return true;
+                }
+                content_bytes.set(content_bytes.get() + line.content().len());
+                if content_bytes.get() > max_bytes {
+                    entry.truncated = true;
+                    return true;
+                }
+
+                if let Some(last_hunk) = entry.hunks.last_mut() {
+                    let content = String::from_utf8_lossy(line.content());
+                    last_hunk.content.push_str(&format!("{}{}\n",
+                        line.origin() as char,
+                        content.trim_end()
+                    ));
+                    last_hunk.lines.push(DiffLine {
+                        origin: line.origin(),
+                        old_lineno: line.old_lineno(),
+                        new_lineno: line.new_lineno(),
+                        content: content.trim_end_matches(&['\r', '\n'][..]).to_string(),
+                        spans: Vec::new(),
+                        tokens: Vec::new(),
+                    });
+                }
+            }
+            true
+        }),
+    )?;
+
+    if let Some(entry) = current_entry.into_inner() {
+        diff_entries.push(entry);
+    }
+
+    // foreach visits every delta in order, so entries line up with deltas
+    for (entry, delta) in diff_entries.iter_mut().zip(diff.deltas()) {
+        if matches!(entry.status, DiffStatus::Renamed | DiffStatus::Copied) {
+            entry.similarity = Some(estimate_similarity(repo, &delta, entry.deletions));
+        }
+        for hunk in &mut entry.hunks {
+            inline_diff::annotate_lines(&mut hunk.lines, options.inline_granularity);
+        }
+        if entry.is_binary {
+            entry.old_image = diff_file_image(repo, &delta.old_file());
+            entry.new_image = diff_file_image(repo, &delta.new_file());
+        } else if options.highlight {
+            highlight_diff_entry(repo, &delta, entry);
+        }
+    }
+
+    Ok(diff_entries)
+}
+
+fn diff_file_image(repo: &Repository, file: &git2::DiffFile) -> Option<ImageInfo> {
+    if !file.exists() {
+        return None;
+    }
+    if let Ok(blob) = repo.find_blob(file.id()) {
+        return image_info(blob.content());
+    }
+
+    // Only the header is needed for the working directory version
+    let full_path = repo.workdir()?.join(file.path()?);
+    let mut header = Vec::with_capacity(IMAGE_HEADER_BYTES);
+    std::fs::File::open(&full_path).ok()?
+        .take(IMAGE_HEADER_BYTES as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    let format = imagesize::image_type(&header).ok()?;
+    let size = imagesize::size(&full_path).ok()?;
+    Some(ImageInfo {
+        format: format!("{:?}", format).to_lowercase(),
+        width: size.width,
+        height: size.height,
+    })
+}
+
+fn image_info(data: &[u8]) -> Option<ImageInfo> {
+    let format = imagesize::image_type(data).ok()?;
+    let size = imagesize::blob_size(data).ok()?;
+    Some(ImageInfo {
+        format: format!("{:?}", format).to_lowercase(),
+        width: size.width,
+        height: size.height,
+    })
+}
+
+fn highlight_diff_entry(repo: &Repository, delta: &git2::DiffDelta, entry: &mut DiffEntry) {
+    let old_tokens = highlight_diff_file(repo, &delta.old_file());
+    let new_tokens = highlight_diff_file(repo, &delta.new_file());
+
+    for line in entry.hunks.iter_mut().flat_map(|hunk| hunk.lines.iter_mut()) {
+        let (tokens, lineno) = match line.origin {
+            '-' => (&old_tokens, line.old_lineno),
+            _ => (&new_tokens, line.new_lineno),
+        };
+        if let (Some(tokens), Some(lineno)) = (tokens, lineno) {
+            if let Some(line_tokens) = tokens.get(lineno.saturating_sub(1) as usize) {
+                line.tokens = line_tokens.clone();
+            }
+        }
+    }
+}
+
+fn highlight_diff_file(repo: &Repository, file: &git2::DiffFile) -> Option<Arc<LineTokens>> {
+    if !file.exists() {
+        return None;
+    }
+    let path = file.path()?;
+
+    if let Ok(blob) = repo.find_blob(file.id()) {
+        if blob.size() > DEFAULT_MAX_FILE_BYTES {
+            return None;
+        }
+        return syntax_highlight::highlight_blob(blob.id(), path, blob.content());
+    }
+
+    // Working directory files aren't in the object database, so hash them to
+    // get a cache key
+    let full_path = repo.workdir()?.join(path);
+    if std::fs::metadata(&full_path).ok()?.len() > DEFAULT_MAX_FILE_BYTES as u64 {
+        return None;
+    }
+    let content = std::fs::read(&full_path).ok()?;
+    let id = git2::Oid::hash_object(git2::ObjectType::Blob, &content).ok()?;
+    syntax_highlight::highlight_blob(id, path, &content)
+}
+
+// Tree diff with renames detected, as file history follows them
+fn renames_diff<'r>(repo: &'r Repository, old_tree: Option<&git2::Tree>, new_tree: &git2::Tree) -> Result<git2::Diff<'r>, GitError> {
+    let mut diff = repo.diff_tree_to_tree(old_tree, Some(new_tree), None)?;
+    let mut find_opts = git2::DiffFindOptions::new();
+    find_opts.renames(true);
+    diff.find_similar(Some(&mut find_opts))?;
+    Ok(diff)
+}
+
+// The name `path` in `tree` had in `parent`, if the file was renamed since
+fn renamed_from(repo: &Repository, parent: &git2::Commit, tree: &git2::Tree, path: &Path) -> Result<Option<PathBuf>, GitError> {
+    let parent_tree = parent.tree()?;
+    if parent_tree.get_path(path).is_ok() {
+        return Ok(None);
+    }
+    let diff = renames_diff(repo, Some(&parent_tree), tree)?;
+    let old_path = diff.deltas()
+        .find(|delta| delta.status() == git2::Delta::Renamed && delta.new_file().path() == Some(path))
+        .and_then(|delta| delta.old_file().path().map(Path::to_path_buf));
+    Ok(old_path)
+}
+
+// libgit2 computes a similarity score during find_similar but git2 doesn't
+// expose it, so estimate it from the share of old lines that survived
+fn estimate_similarity(repo: &Repository, delta: &git2::DiffDelta, deletions: usize) -> u16 {
+    let (old_file, new_file) = (delta.old_file(), delta.new_file());
+    if old_file.id() == new_file.id() && !old_file.id().is_zero() {
+        return 100;
+    }
+
+    match (diff_file_line_count(repo, &old_file), diff_file_line_count(repo, &new_file)) {
+        (Some(old_lines), Some(new_lines)) if old_lines.max(new_lines) > 0 => {
+            let unchanged = old_lines.saturating_sub(deletions);
+            (unchanged * 100 / old_lines.max(new_lines)) as u16
+        }
+        _ => 0,
+    }
+}
+
+// Lines in one side of a delta, read from the working directory when that
+// side isn't a stored blob
+fn diff_file_line_count(repo: &Repository, file: &git2::DiffFile) -> Option<usize> {
+    let line_count = |content: &[u8]| {
+        let newlines = content.iter().filter(|&&b| b == b'\n').count();
+        newlines + usize::from(!content.is_empty() && !content.ends_with(b"\n"))
+    };
+    if let Ok(blob) = repo.find_blob(file.id()) {
+        return Some(line_count(blob.content()));
+    }
+    let content = std::fs::read(repo.workdir()?.join(file.path()?)).ok()?;
+    Some(line_count(&content))
+}
+
+// File name part derived from a commit summary, like format-patch does
+fn patch_file_slug(summary: &str) -> String {
+    let mut slug = String::new();
+    for c in summary.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.len() >= 52 {
+            break;
+        }
+    }
+    let slug = slug.trim_matches(|c| c == '-' || c == '.').to_string();
+    if slug.is_empty() { "patch".to_string() } else { slug }
+}
+
+// Returns None for binary data, otherwise the name of the detected text encoding
+fn detect_encoding(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return Some("utf-8-bom");
+    }
+    if data.starts_with(&[0xFF, 0xFE]) {
+        return Some("utf-16le");
+    }
+    if data.starts_with(&[0xFE, 0xFF]) {
+        return Some("utf-16be");
+    }
+
+    // Same heuristic as git: a NUL byte in the first 8000 bytes means binary
+    if data.iter().take(8000).any(|&b| b == 0) {
+        return None;
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(_) => Some("utf-8"),
+        // A multi-byte sequence cut off at the end is still UTF-8
+        Err(e) if e.error_len().is_none() => Some("utf-8"),
+        Err(_) => Some("latin1"),
+    }
+}
+
+// Length of the byte order mark that detect_encoding found
+fn bom_len(encoding: &str) -> usize {
+    match encoding {
+        "utf-8-bom" => 3,
+        "utf-16le" | "utf-16be" => 2,
+        _ => 0,
+    }
+}
+
+// `data` is the text without its byte order mark
+fn decode_text(data: &[u8], encoding: &str) -> String {
+    match encoding {
+        "utf-16le" | "utf-16be" => {
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| match encoding {
+                    "utf-16le" => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        "latin1" => data.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `files` to the working tree and commits them on HEAD
+    fn commit(repo: &Repository, files: &[(&str, &[u8])], message: &str) -> git2::Oid {
+        let workdir = repo.workdir().unwrap();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            let full_path = workdir.join(path);
+            std::fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            std::fs::write(&full_path, content).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).unwrap()
+    }
+
+    // Commits a tree of top-level files without touching HEAD
+    fn commit_tree(repo: &Repository, parents: &[git2::Oid], files: &[(&str, &str)], message: &str) -> git2::Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (path, content) in files {
+            builder.insert(path, repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<_> = parents.iter().map(|id| repo.find_commit(*id).unwrap()).collect();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        repo.commit(None, &signature, &signature, message, &tree, &parents).unwrap()
+    }
+
+    fn repository(files: &[(&str, &[u8])]) -> (tempfile::TempDir, GitRepo) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        commit(&repo, files, "Initial commit");
+        let repo = GitRepo::open(dir.path().to_str().unwrap(), None).unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn truncates_after_the_byte_order_mark() {
+        let (_dir, repo) = repository(&[
+            ("bom.txt", b"\xEF\xBB\xBFhello"),
+            ("utf16.txt", b"\xFF\xFEh\0i\0"),
+        ]);
+
+        for max_bytes in 0..3 {
+            let file = repo.read_file_at("HEAD", "bom.txt", Some(max_bytes), false).unwrap();
+            assert_eq!(file.content.as_deref(), Some(&"hello"[..max_bytes]));
+            assert!(file.truncated);
+        }
+        let file = repo.read_file_at("HEAD", "bom.txt", Some(5), false).unwrap();
+        assert_eq!((file.content.as_deref(), file.truncated), (Some("hello"), false));
+
+        let file = repo.read_file_at("HEAD", "utf16.txt", Some(1), false).unwrap();
+        assert_eq!((file.content.as_deref(), file.truncated), (Some(""), true));
+        let file = repo.read_file_at("HEAD", "utf16.txt", Some(2), false).unwrap();
+        assert_eq!(file.content.as_deref(), Some("h"));
+        assert_eq!(file.encoding.as_deref(), Some("utf-16le"));
+    }
+
+    #[test]
+    fn lists_tree_entries_with_blob_sizes() {
+        let (_dir, repo) = repository(&[("src/main.rs", b"fn main() {}\n"), ("README.md", b"# Readme\n")]);
+
+        let entries = repo.list_tree("HEAD", None).unwrap();
+        let listed: Vec<_> = entries.iter().map(|entry| (entry.path.as_str(), entry.kind.as_str(), entry.size)).collect();
+        assert_eq!(listed, vec![("src", "tree", None), ("README.md", "blob", Some(9))]);
+
+        let entries = repo.list_tree("HEAD", Some("src/")).unwrap();
+        assert_eq!((entries[0].path.as_str(), entries[0].size), ("src/main.rs", Some(13)));
+    }
+
+    #[test]
+    fn exports_only_working_directory_files_inside_the_repository() {
+        let (dir, repo) = repository(&[("image.png", b"old")]);
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.png"), b"secret").unwrap();
+        std::fs::write(dir.path().join("image.png"), b"new").unwrap();
+        let assets = dir.path().join(".git/assets");
+
+        let exported = repo.export_binary_versions(None, None, "image.png", &assets).unwrap();
+        assert_eq!(std::fs::read(exported.new_file.unwrap()).unwrap(), b"new");
+
+        let escapes = [
+            "../secret.png".to_string(),
+            outside.path().join("secret.png").to_string_lossy().into_owned(),
+        ];
+        for file_path in &escapes {
+            assert!(repo.export_binary_versions(None, None, file_path, &assets).is_err(), "{}", file_path);
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outside.path().join("secret.png"), dir.path().join("link.png")).unwrap();
+            assert!(repo.export_binary_versions(None, None, "link.png", &assets).is_err());
+        }
+    }
+
+    #[test]
+    fn estimates_the_similarity_of_working_directory_renames() {
+        let content: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+        let (dir, repo) = repository(&[("old.txt", content.as_bytes())]);
+        std::fs::remove_file(dir.path().join("old.txt")).unwrap();
+        std::fs::write(dir.path().join("new.txt"), content.replace("line 10", "line ten")).unwrap();
+
+        let entries = repo.view_diff(&DiffViewOptions::default()).unwrap();
+
+        assert_eq!(entries.len(), 1, "{:?}", entries);
+        assert!(matches!(entries[0].status, DiffStatus::Renamed));
+        assert_eq!(entries[0].new_path.as_deref(), Some("new.txt"));
+        assert_eq!(entries[0].similarity, Some(90));
+    }
+
+    #[test]
+    fn follows_renames_on_either_side_of_a_merge() {
+        let (_dir, repo) = repository(&[("a.txt", b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n")]);
+        let git = &repo.repo;
+        let base = git.head().unwrap().peel_to_commit().unwrap().id();
+        let renamed = commit_tree(git, &[base], &[("b.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n")], "Rename");
+        let edited = commit_tree(git, &[renamed], &[("b.txt", "1\n2\n3\n4\n5\n6\n7\n8\n9\nten\n")], "Edit on the side");
+        let main = commit_tree(git, &[base], &[("a.txt", "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n")], "Edit on main");
+        let merge = commit_tree(git, &[main, edited], &[("b.txt", "one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n")], "Merge");
+
+        let history = repo.file_history("b.txt", Some(&merge.to_string()), None).unwrap();
+
+        let mut listed: Vec<_> = history.iter()
+            .map(|entry| (entry.summary.as_str(), entry.path.as_str(), entry.old_path.as_deref(), entry.status))
+            .collect();
+        listed.sort_by_key(|(summary, ..)| *summary);
+        assert_eq!(listed, vec![
+            ("Edit on main", "a.txt", None, DiffStatus::Modified),
+            ("Edit on the side", "b.txt", None, DiffStatus::Modified),
+            ("Initial commit", "a.txt", None, DiffStatus::Added),
+            ("Merge", "b.txt", Some("a.txt"), DiffStatus::Renamed),
+            ("Rename", "b.txt", Some("a.txt"), DiffStatus::Renamed),
+        ]);
+        assert_eq!(history[0].summary, "Merge");
+        assert_eq!(history.last().unwrap().summary, "Initial commit");
+    }
+
+    #[test]
+    fn exports_two_and_three_dot_ranges() {
+        let (dir, repo) = repository(&[("a.txt", b"base\n")]);
+        let git = &repo.repo;
+        let base = git.head().unwrap().peel_to_commit().unwrap().id();
+        let left = commit_tree(git, &[base], &[("a.txt", "left\n")], "Left one");
+        let left = commit_tree(git, &[left], &[("a.txt", "left two\n")], "Left two");
+        let right = commit_tree(git, &[base], &[("b.txt", "right\n")], "Right one");
+        let exported = |range: &str| {
+            let dest = dir.path().join(".git").join(range.replace('.', "_"));
+            repo.export_patches(range, &dest).unwrap().iter()
+                .map(|file| Path::new(file).file_name().unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(exported(&format!("{}..{}", right, left)), vec!["0001-Left-one.patch", "0002-Left-two.patch"]);
+        let mut symmetric = exported(&format!("{}...{}", right, left));
+        symmetric.sort();
+        assert_eq!(symmetric.len(), 3, "{:?}", symmetric);
+        assert!(symmetric.iter().any(|file| file.ends_with("Right-one.patch")), "{:?}", symmetric);
+        assert!(repo.export_patches(&format!("{}...missing", left), dir.path()).is_err());
+    }
+
+    #[test]
+    fn checks_each_file_of_a_patch_on_its_own() {
+        let lines = |changed: &str| format!("1\n2\n3\n{}\n5\n6\n7\n", changed);
+        let (dir, repo) = repository(&[("a.txt", lines("4").as_bytes()), ("b.txt", lines("4").as_bytes())]);
+        for file in ["a.txt", "b.txt"] {
+            std::fs::write(dir.path().join(file), lines("four")).unwrap();
+        }
+        let patch_file = dir.path().join(".git/change.patch");
+        repo.export_working_diff(&patch_file).unwrap();
+        for file in ["a.txt", "b.txt"] {
+            std::fs::write(dir.path().join(file), lines("4")).unwrap();
+        }
+        let applies = || {
+            let report = repo.check_patch(&patch_file, PatchTarget::Workdir).unwrap();
+            let hunks: Vec<_> = report.hunks.iter().map(|hunk| (hunk.file.clone().unwrap(), hunk.applies)).collect();
+            (report.applies_cleanly, hunks)
+        };
+        let hunks = |a: bool, b: bool| vec![("a.txt".to_string(), a), ("b.txt".to_string(), b)];
+
+        assert_eq!(applies(), (true, hunks(true, true)));
+
+        std::fs::write(dir.path().join("a.txt"), lines("conflict")).unwrap();
+        assert_eq!(applies(), (false, hunks(false, true)));
+
+        std::fs::remove_file(dir.path().join("a.txt")).unwrap();
+        assert_eq!(applies(), (false, hunks(false, true)));
+    }
+}
@@ -1,22 +1,116 @@
 use oauth2::{
     basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, Scope,
-    TokenUrl, AuthorizationCode, TokenResponse, CsrfToken,
+    TokenUrl, AuthorizationCode, TokenResponse, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier,
 };
 use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::sync::Arc;
+use parking_lot::Mutex as PLMutex;
 use std::net::TcpListener;
 use tauri::Window;
 use tokio::io::{AsyncWriteExt, BufReader, AsyncBufReadExt};
 use tokio::net::TcpStream;
-use std::env;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::borrow::Cow;
+use std::time::Duration;
+use tokio::time::Instant;
 use crate::git_commands::RepositoryStats;
+use crate::settings::{OAuthAppSettings, Settings};
+use crate::accounts::{Account, AccountRegistry};
+use crate::git_commands::GitError;
+use crate::hosting::{
+    Host, HostedRepository, HostingError, HostingProvider, OAuthEndpoints, ProviderKind,
+    PullRequest, PullRequestState,
+};
+
+// Ports the loopback redirect may use; GitHub matches any port on localhost
+// against the registered http://localhost:3000/callback
+const LOOPBACK_PORTS: std::ops::Range<u16> = 3000..3010;
+const LOOPBACK_TIMEOUT: Duration = Duration::from_secs(120);
+// How long a connection to the callback port may take to send its request line
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+// An OAuth app registered on one host. The provider supplies what differs
+// between services (endpoints, scopes, extra parameters); the flows
+// themselves are the standard ones and shared.
+struct OAuthApp {
+    host: Host,
+    provider: Box<dyn HostingProvider>,
+    oauth_client: BasicClient,
+    client_id: String,
+    // Only the browser flow needs it; device login works without
+    has_client_secret: bool,
+    endpoints: OAuthEndpoints,
+}
+
+impl OAuthApp {
+    fn new(settings: &OAuthAppSettings, kind: ProviderKind, client: &reqwest::Client) -> Result<Self, AuthError> {
+        let host = Host::new(kind, settings.host.as_deref(), settings.api_url.as_deref());
+        let provider = host.provider(client.clone());
+        let client_id = settings.client_id.clone()
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| AuthError::OAuth(format!("No client_id configured for {}", host.web_url)))?;
+        let client_secret = settings.client_secret.clone()
+            .filter(|value| !value.is_empty())
+            .map(ClientSecret::new);
+        let has_client_secret = client_secret.is_some();
+        let endpoints = provider.oauth_endpoints();
+
+        let auth_url = AuthUrl::new(endpoints.authorize_url.clone())
+            .map_err(|e| AuthError::OAuth(format!("Invalid authorization endpoint URL: {}", e)))?;
+        let token_url = TokenUrl::new(endpoints.token_url.clone())
+            .map_err(|e| AuthError::OAuth(format!("Invalid token endpoint URL: {}", e)))?;
+
+        let redirect_url = RedirectUrl::new("http://localhost:3000/callback".to_string())
+            .expect("Invalid redirect URL");
+
+        let oauth_client = BasicClient::new(
+            ClientId::new(client_id.clone()),
+            client_secret,
+            auth_url,
+            Some(token_url),
+        )
+        .set_redirect_uri(redirect_url);
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
-pub struct AuthState {
-    pub access_token: Option<String>,
+        Ok(OAuthApp { host, provider, oauth_client, client_id, has_client_secret, endpoints })
+    }
+}
+
+// Response to a device authorization request (RFC 8628 section 3.2)
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: u64,
+    // Minimum seconds between token polls
+    #[serde(default = "default_poll_interval")]
+    pub interval: u64,
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+// What the user needs to finish a device login, sent with "device-code"
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceCodePrompt {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: u64,
+}
+
+// GitHub answers pending polls with 200 and an `error` field rather than a
+// 400, which is why the device flow doesn't go through the oauth2 crate
+#[derive(Debug, Deserialize)]
+struct DeviceTokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+    interval: Option<u64>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -29,40 +123,42 @@ pub enum AuthError {
     Io(#[from] std::io::Error),
     #[error("Tauri error: {0}")]
     Tauri(#[from] tauri::Error),
+    #[error("Account error: {0}")]
+    Accounts(#[from] GitError),
+    #[error(transparent)]
+    Hosting(#[from] HostingError),
+    #[error("Not signed in")]
+    NotAuthenticated,
+    #[error("Authentication timed out")]
+    TimedOut,
+    #[error("Authentication cancelled")]
+    Cancelled,
+    #[error("{login} on {host} is missing the {scope} scope; re-authenticate to grant {scope}")]
+    MissingScope { login: String, host: String, scope: &'static str },
 }
 
 #[derive(Clone)]
-pub struct GitHubAuth {
-    oauth_client: Arc<BasicClient>,
+pub struct HostAuth {
+    // OAuth apps keyed by the web URL of their host
+    apps: Arc<HashMap<String, OAuthApp>>,
     client: reqwest::Client,
-    auth_state: Arc<Mutex<AuthState>>,
+    accounts: Arc<PLMutex<AccountRegistry>>,
     pub is_authenticating: Arc<AtomicBool>,
 }
 
-impl GitHubAuth {
-    pub fn new() -> Self {
-        let client_id = ClientId::new(
-            env::var("GITHUB_CLIENT_ID").expect("Missing GITHUB_CLIENT_ID environment variable"),
-        );
-        let client_secret = ClientSecret::new(
-            env::var("GITHUB_CLIENT_SECRET").expect("Missing GITHUB_CLIENT_SECRET environment variable"),
-        );
-
-        let auth_url = AuthUrl::new("https://github.com/login/oauth/authorize".to_string())
-            .expect("Invalid authorization endpoint URL");
-        let token_url = TokenUrl::new("https://github.com/login/oauth/access_token".to_string())
-            .expect("Invalid token endpoint URL");
-
-        let redirect_url = RedirectUrl::new("http://localhost:3000/callback".to_string())
-            .expect("Invalid redirect URL");
-
-        let oauth_client = BasicClient::new(
-            client_id,
-            Some(client_secret),
-            auth_url,
-            Some(token_url),
-        )
-        .set_redirect_uri(redirect_url);
+impl HostAuth {
+    // Sets up the github.com OAuth app (if configured) and one per GitHub
+    // Enterprise, GitLab and Gitea host. Accounts on hosts without an app can
+    // still be added with a personal access token. An invalid app is logged
+    // and left out, so it doesn't take sign-in on the other hosts down too.
+    pub fn new(settings: &Settings, accounts: Arc<PLMutex<AccountRegistry>>) -> Result<Self, AuthError> {
+        let mut configured = Vec::new();
+        if settings.github.client_id.as_deref().is_some_and(|id| !id.is_empty()) {
+            configured.push((&settings.github, ProviderKind::GitHub));
+        }
+        configured.extend(settings.github_enterprise.iter().map(|app| (app, ProviderKind::GitHub)));
+        configured.extend(settings.gitlab.iter().map(|app| (app, ProviderKind::GitLab)));
+        configured.extend(settings.gitea.iter().map(|app| (app, ProviderKind::Gitea)));
 
         let mut headers = HeaderMap::new();
         headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
@@ -70,200 +166,426 @@ impl GitHubAuth {
 
         let client = reqwest::Client::builder()
             .default_headers(headers)
-            .build()
-            .expect("Failed to create HTTP client");
+            .build()?;
 
-        Self {
-            oauth_client: Arc::new(oauth_client),
+        let mut apps = HashMap::new();
+        for (app_settings, provider) in configured {
+            match OAuthApp::new(app_settings, provider, &client) {
+                Ok(app) => {
+                    apps.insert(app.host.web_url.clone(), app);
+                }
+                Err(e) => {
+                    let host = app_settings.host.as_deref().unwrap_or("the default host");
+                    eprintln!("Skipping the {:?} OAuth app for {}: {}", provider, host, e);
+                }
+            }
+        }
+
+        Ok(Self {
+            apps: Arc::new(apps),
             client,
-            auth_state: Arc::new(Mutex::new(AuthState::default())),
+            accounts,
             is_authenticating: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn app(&self, provider: ProviderKind, host: Option<&str>) -> Result<&OAuthApp, AuthError> {
+        let web_url = Host::new(provider, host, None).web_url;
+        self.apps.get(&web_url)
+            .ok_or_else(|| AuthError::OAuth(format!("No OAuth app is configured for {}", web_url)))
+    }
+
+    // The host as configured in settings, or derived from its name when
+    // there is no OAuth app for it
+    pub fn host(&self, provider: ProviderKind, host: Option<&str>) -> Host {
+        match self.app(provider, host) {
+            Ok(app) => app.host.clone(),
+            Err(_) => Host::new(provider, host, None),
         }
     }
 
-    pub fn set_access_token(&self, token: String) {
-        if let Ok(mut state) = self.auth_state.lock() {
-            state.access_token = Some(token);
-            self.is_authenticating.store(false, Ordering::SeqCst);
+    fn provider(&self, host: &Host) -> Box<dyn HostingProvider> {
+        host.provider(self.client.clone())
+    }
+
+    // The given account, or the default account, with its token
+    pub fn account(&self, account_id: Option<&str>) -> Result<(Account, String), AuthError> {
+        let accounts = self.accounts.lock();
+        let account = match account_id {
+            Some(id) => accounts.get(id),
+            None => accounts.default_account(),
+        }
+        .ok_or(AuthError::NotAuthenticated)?;
+        let token = accounts.token(&account.id)?.ok_or(AuthError::NotAuthenticated)?;
+        Ok((account, token))
+    }
+
+    // Looks up who the token belongs to on `host` and keeps it as that
+    // user's account
+    pub async fn add_account(&self, token: String, host: &Host) -> Result<Account, AuthError> {
+        let user = self.provider(host).current_user(&token).await?;
+        Ok(self.accounts.lock().add(user, host.clone(), &token)?)
+    }
+
+    // Signs an account out, the default one if none is given
+    pub fn remove_account(&self, account_id: Option<&str>) -> Result<(), AuthError> {
+        let mut accounts = self.accounts.lock();
+        let id = match account_id {
+            Some(id) => id.to_string(),
+            None => match accounts.default_account() {
+                Some(account) => account.id,
+                None => return Ok(()),
+            },
+        };
+        Ok(accounts.remove(&id)?)
+    }
+
+    // Turns the single github.com token kept before accounts existed into an
+    // account. Only a token GitHub rejects with 401 is dropped; when GitHub
+    // can't be reached or answers with another error, the token is kept for
+    // the next start and sign-in carries on without it.
+    pub async fn migrate_legacy_token(&self) -> Result<(), AuthError> {
+        let token = match self.accounts.lock().legacy_token()? {
+            Some(token) => token,
+            None => return Ok(()),
+        };
+        match self.add_account(token, &self.host(ProviderKind::GitHub, None)).await {
+            Ok(_) | Err(AuthError::Hosting(HostingError::Unauthorized { .. })) => {
+                self.accounts.lock().clear_legacy_token()?;
+            }
+            Err(e) => eprintln!("Could not migrate the stored GitHub token, keeping it: {}", e),
         }
+        Ok(())
+    }
+
+    // Binds the first free port in the range registered with the OAuth app
+    fn bind_loopback() -> Option<(TcpListener, u16)> {
+        LOOPBACK_PORTS.clone().find_map(|port| {
+            TcpListener::bind(("127.0.0.1", port)).ok().map(|listener| (listener, port))
+        })
+    }
+
+    // Whether the browser redirect flow is usable on the host; without a
+    // client secret only device login is
+    pub fn has_client_secret(&self, provider: ProviderKind, host: Option<&str>) -> bool {
+        self.app(provider, host).is_ok_and(|app| app.has_client_secret)
     }
 
-    fn find_free_port() -> Option<u16> {
-        for port in 3000..3010 {
-            if TcpListener::bind(("127.0.0.1", port)).is_ok() {
-                return Some(port);
+    // Authorization code flow with PKCE in the user's browser, see
+    // browser_login
+    pub async fn start_login(&self, window: Window, provider: ProviderKind, host: Option<&str>) -> Result<String, AuthError> {
+        let app = self.app(provider, host)?;
+        let result = self.browser_login(app, |auth_url| {
+            Ok(window.eval(&format!("window.open('{}', '_blank')", auth_url))?)
+        }).await;
+
+        match result {
+            Ok(account) => {
+                window.emit("auth-success", &account)?;
+                Ok(format!("Signed in as {}", account.login))
+            }
+            Err(AuthError::TimedOut) => {
+                window.emit("auth-timeout", "Authentication timed out").ok();
+                Err(AuthError::TimedOut)
+            }
+            Err(e) => {
+                window.emit("auth-error", e.to_string()).ok();
+                Err(e)
             }
         }
-        None
     }
 
-    pub async fn start_login(&self, window: Window) -> Result<String, AuthError> {
-        let port = Self::find_free_port().ok_or_else(|| 
+    // Opens the authorization URL with `open_browser` and waits for the
+    // redirect back to a listener on localhost. The callback must carry the
+    // `state` we sent; anything else that reaches the port (favicon
+    // requests, forged callbacks) is answered and ignored. Each connection
+    // is read in its own task, so sockets that browsers open ahead of time
+    // and never use don't hold up the real callback.
+    async fn browser_login(
+        &self,
+        app: &OAuthApp,
+        open_browser: impl FnOnce(&str) -> Result<(), AuthError>,
+    ) -> Result<Account, AuthError> {
+        if !app.has_client_secret {
+            return Err(AuthError::OAuth(format!(
+                "No client_secret is configured for {}, use device login instead",
+                app.host.web_url
+            )));
+        }
+
+        let (listener, port) = Self::bind_loopback().ok_or_else(||
             AuthError::OAuth("No available ports. Please try again in a moment.".to_string())
         )?;
-        
-        let listener = TcpListener::bind(("127.0.0.1", port))?;
         listener.set_nonblocking(true)?;
-        
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+
         let redirect_url = RedirectUrl::new(format!("http://localhost:{}/callback", port))
             .map_err(|e| AuthError::OAuth(e.to_string()))?;
-        
-        let csrf_token = CsrfToken::new_random();
-        let (auth_url, _csrf_token) = self.oauth_client
-            .authorize_url(|| csrf_token)
+
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let mut request = app.oauth_client
+            .authorize_url(CsrfToken::new_random)
             .set_redirect_uri(Cow::Owned(redirect_url.clone()))
-            .add_scope(Scope::new("repo".to_string()))
-            .add_scope(Scope::new("user".to_string()))
-            .add_extra_param("prompt", "select_account")
-            .url();
-
-        window.eval(&format!("window.open('{}', '_blank')", auth_url))?;
-
-        let oauth_client = self.oauth_client.clone();
-        let auth_state = self.auth_state.clone();
-        let window_clone = window.clone();
-        
-        let timeout = tokio::time::sleep(tokio::time::Duration::from_secs(120));
-        let auth_task = async move {
+            .set_pkce_challenge(pkce_challenge)
+            .add_scopes(app.provider.oauth_scopes().iter().map(|scope| Scope::new(scope.to_string())));
+        for (name, value) in app.provider.authorize_params() {
+            request = request.add_extra_param(*name, *value);
+        }
+        let (auth_url, csrf_token) = request.url();
+
+        self.is_authenticating.store(true, Ordering::SeqCst);
+        if let Err(e) = open_browser(auth_url.as_str()) {
+            self.is_authenticating.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        let auth_task = async {
+            let (requests_tx, mut requests) = tokio::sync::mpsc::channel::<(TcpStream, String)>(8);
             loop {
-                match listener.accept() {
-                    Ok((stream, _)) => {
-                        let mut stream = TcpStream::from_std(stream)?;
-                        let mut reader = BufReader::new(&mut stream);
-                        let mut request_line = String::new();
-                        
-                        reader.read_line(&mut request_line).await?;
-                        
-                        if let Some(code) = request_line
-                            .split_whitespace()
-                            .nth(1)
-                            .and_then(|path| path.split("code=").nth(1))
-                            .map(|code| code.split('&').next().unwrap_or(code))
-                        {
-                            let token = oauth_client
-                                .exchange_code(AuthorizationCode::new(code.to_string()))
-                                .set_redirect_uri(Cow::Owned(redirect_url.clone()))
-                                .request_async(oauth2::reqwest::async_http_client)
-                                .await
-                                .map_err(|e| AuthError::OAuth(e.to_string()))?;
-
-                            let response = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n\
-                                <html><body><h1>Authentication successful!</h1><p>You can close this window now.</p></body></html>";
-                            stream.write_all(response.as_bytes()).await?;
-                            
-                            if let Ok(mut state) = auth_state.lock() {
-                                let token_secret = token.access_token().secret().clone();
-                                state.access_token = Some(token_secret.clone());
-                                window_clone.eval(&format!("localStorage.setItem('github_token', '{}')", token_secret)).ok();
+                let (mut stream, target) = tokio::select! {
+                    accepted = self.accept_loopback(&listener) => {
+                        let mut stream = match accepted? {
+                            Some(stream) => stream,
+                            None => return Err(AuthError::Cancelled),
+                        };
+                        let requests_tx = requests_tx.clone();
+                        tokio::spawn(async move {
+                            match tokio::time::timeout(REQUEST_READ_TIMEOUT, read_request_target(&mut stream)).await {
+                                Ok(Ok(target)) => {
+                                    let _ = requests_tx.send((stream, target)).await;
+                                }
+                                Ok(Err(e)) => eprintln!("Ignoring unreadable request on the OAuth callback port: {}", e),
+                                Err(_) => {}
                             }
-                            window_clone.emit("auth-success", ()).map_err(|e| AuthError::Tauri(e))?;
-                            return Ok(());
-                        }
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                        });
                         continue;
                     }
-                    Err(e) => {
-                        return Err(AuthError::Io(e));
+                    Some(request) = requests.recv() => request,
+                };
+
+                match parse_callback(&target) {
+                    CallbackRequest::Unrelated => {
+                        respond(&mut stream, "404 Not Found", "Not found").await;
+                    }
+                    CallbackRequest::Code { state, .. } | CallbackRequest::Denied { state, .. }
+                        if state != *csrf_token.secret() =>
+                    {
+                        respond(&mut stream, "400 Bad Request", "This sign-in link is invalid or has expired.").await;
+                    }
+                    CallbackRequest::Denied { reason, .. } => {
+                        respond(&mut stream, "200 OK", &format!("Authentication failed: {}", reason)).await;
+                        return Err(AuthError::OAuth(reason));
+                    }
+                    CallbackRequest::Code { code, .. } => {
+                        let token = app.oauth_client
+                            .exchange_code(AuthorizationCode::new(code))
+                            .set_redirect_uri(Cow::Owned(redirect_url.clone()))
+                            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier.secret().clone()))
+                            .request_async(oauth2::reqwest::async_http_client)
+                            .await;
+
+                        return match token {
+                            Ok(token) => {
+                                respond(&mut stream, "200 OK", "Authentication successful! You can close this window now.").await;
+                                self.add_account(token.access_token().secret().clone(), &app.host).await
+                            }
+                            Err(e) => {
+                                respond(&mut stream, "200 OK", "Authentication failed. Please return to SimpleGit and try again.").await;
+                                Err(AuthError::OAuth(e.to_string()))
+                            }
+                        };
                     }
                 }
             }
         };
 
-        tokio::select! {
-            result = auth_task => {
-                match result {
-                    Ok(_) => Ok("Authentication successful".to_string()),
-                    Err(e) => {
-                        window.emit("auth-error", e.to_string()).ok();
-                        Err(e)
-                    }
-                }
+        let result = tokio::select! {
+            result = auth_task => result,
+            _ = tokio::time::sleep(LOOPBACK_TIMEOUT) => Err(AuthError::TimedOut),
+        };
+        self.is_authenticating.store(false, Ordering::SeqCst);
+        result
+    }
+
+    // Waits for the next connection, or None once the login is cancelled.
+    // Returning drops the listener, which frees the port.
+    async fn accept_loopback(&self, listener: &tokio::net::TcpListener) -> Result<Option<TcpStream>, AuthError> {
+        loop {
+            if !self.is_authenticating.load(Ordering::SeqCst) {
+                return Ok(None);
             }
-            _ = timeout => {
-                window.emit("auth-timeout", "Authentication timed out").ok();
-                Err(AuthError::OAuth("Authentication timed out".to_string()))
+            if let Ok(accepted) = tokio::time::timeout(Duration::from_millis(250), listener.accept()).await {
+                return Ok(Some(accepted?.0));
             }
         }
     }
 
-    pub async fn handle_callback(
-        &self,
-        code: String,
-    ) -> Result<(), AuthError> {
-        let token = self.oauth_client
-            .exchange_code(AuthorizationCode::new(code))
-            .request_async(oauth2::reqwest::async_http_client)
-            .await
-            .map_err(|e| AuthError::OAuth(e.to_string()))?;
+    // Device authorization flow: the user enters a short code on the host while
+    // the app polls for the token. Needs neither a client secret nor a local
+    // port, but Gitea doesn't offer it.
+    pub async fn start_device_login(&self, window: Window, provider: ProviderKind, host: Option<&str>) -> Result<String, AuthError> {
+        self.is_authenticating.store(true, Ordering::SeqCst);
 
-        if let Ok(mut state) = self.auth_state.lock() {
-            state.access_token = Some(token.access_token().secret().clone());
+        let result = async {
+            let device = self.request_device_code(provider, host).await?;
+            window.emit("device-code", DeviceCodePrompt {
+                user_code: device.user_code.clone(),
+                verification_uri: device.verification_uri.clone(),
+                expires_in: device.expires_in,
+            })?;
+            // The URI comes from the server, so quote it as a JS string literal
+            let uri = serde_json::to_string(&device.verification_uri)
+                .map_err(|e| AuthError::OAuth(e.to_string()))?;
+            window.eval(&format!("window.open({}, '_blank')", uri))?;
+            let token = self.poll_device_token(provider, host, &device).await?;
+            self.add_account(token, &self.host(provider, host)).await
+        }.await;
+
+        self.is_authenticating.store(false, Ordering::SeqCst);
+        match result {
+            Ok(account) => {
+                window.emit("auth-success", &account)?;
+                Ok(format!("Signed in as {}", account.login))
+            }
+            Err(AuthError::TimedOut) => {
+                window.emit("auth-timeout", "Authentication timed out").ok();
+                Err(AuthError::TimedOut)
+            }
+            Err(e) => {
+                window.emit("auth-error", e.to_string()).ok();
+                Err(e)
+            }
         }
-        Ok(())
     }
 
-    pub async fn list_repositories(&self) -> Result<Vec<String>, AuthError> {
-        let token = {
-            let state = self.auth_state.lock().unwrap();
-            state.access_token
-                .clone()
-                .ok_or_else(|| AuthError::OAuth("Not authenticated".to_string()))?
-        };
-
+    pub async fn request_device_code(&self, provider: ProviderKind, host: Option<&str>) -> Result<DeviceAuthorization, AuthError> {
+        let app = self.app(provider, host)?;
+        let device_code_url = app.endpoints.device_code_url.as_ref().ok_or_else(|| {
+            AuthError::OAuth(format!(
+                "{} has no device login, configure a client_secret for it instead",
+                app.host.web_url
+            ))
+        })?;
+        let scopes = app.provider.oauth_scopes().join(" ");
         let response = self.client
-            .get("https://api.github.com/user/repos")
-            .header(
-                "Authorization",
-                format!("Bearer {}", token),
-            )
+            .post(device_code_url)
+            .form(&[("client_id", app.client_id.as_str()), ("scope", scopes.as_str())])
             .send()
-            .await?
-            .json::<Vec<serde_json::Value>>()
             .await?;
 
-        Ok(response
+        let status = response.status();
+        let body = response.json::<serde_json::Value>().await?;
+        if !status.is_success() || body.get("error").is_some() {
+            let message = body["error_description"].as_str()
+                .or_else(|| body["error"].as_str())
+                .unwrap_or("unexpected response");
+            return Err(AuthError::OAuth(format!("Device authorization failed: {}", message)));
+        }
+        serde_json::from_value(body)
+            .map_err(|e| AuthError::OAuth(format!("Invalid device authorization response: {}", e)))
+    }
+
+    // Polls the token endpoint until the user approves or denies the code, the
+    // code expires or the login is cancelled. Timed with tokio's clock so
+    // that tests can skip the waits.
+    pub async fn poll_device_token(&self, provider: ProviderKind, host: Option<&str>, device: &DeviceAuthorization) -> Result<String, AuthError> {
+        let app = self.app(provider, host)?;
+        let deadline = Instant::now() + Duration::from_secs(device.expires_in);
+        let mut interval = Duration::from_secs(device.interval.max(1));
+
+        loop {
+            tokio::time::sleep(interval).await;
+            if !self.is_authenticating.load(Ordering::SeqCst) {
+                return Err(AuthError::Cancelled);
+            }
+            if Instant::now() >= deadline {
+                return Err(AuthError::TimedOut);
+            }
+
+            let response = self.client
+                .post(&app.endpoints.token_url)
+                .form(&[
+                    ("client_id", app.client_id.as_str()),
+                    ("device_code", device.device_code.as_str()),
+                    ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                ])
+                .send()
+                .await?
+                .json::<DeviceTokenResponse>()
+                .await?;
+
+            if let Some(token) = response.access_token {
+                return Ok(token);
+            }
+            match response.error.as_deref() {
+                Some("authorization_pending") => {}
+                Some("slow_down") => {
+                    interval = response.interval
+                        .map(Duration::from_secs)
+                        .unwrap_or(interval + Duration::from_secs(5));
+                }
+                Some("expired_token") => return Err(AuthError::TimedOut),
+                Some("access_denied") => {
+                    return Err(AuthError::OAuth("Access was denied".to_string()));
+                }
+                Some(error) => {
+                    return Err(AuthError::OAuth(
+                        response.error_description.unwrap_or_else(|| error.to_string()),
+                    ));
+                }
+                None => return Err(AuthError::OAuth("Unexpected token response".to_string())),
+            }
+        }
+    }
+
+    pub async fn list_repositories(&self, account_id: Option<&str>) -> Result<Vec<String>, AuthError> {
+        Ok(self.list_hosted_repositories(account_id).await?
             .into_iter()
-            .filter_map(|repo| repo["full_name"].as_str().map(String::from))
+            .map(|repo| repo.full_name)
             .collect())
     }
 
-    pub fn cancel_auth(&self) {
-        self.is_authenticating.store(false, Ordering::SeqCst);
-        if let Ok(mut state) = self.auth_state.lock() {
-            state.access_token = None;
-        }
-        self.force_cleanup_port();
+    pub async fn list_hosted_repositories(&self, account_id: Option<&str>) -> Result<Vec<HostedRepository>, AuthError> {
+        let (account, token) = self.account(account_id)?;
+        Ok(self.provider(&account.host).list_repositories(&token).await?)
     }
 
-    fn force_cleanup_port(&self) {
-        #[cfg(windows)]
-        {
-            let _ = std::process::Command::new("cmd")
-                .args(&["/C", "for /f \"tokens=5\" %a in ('netstat -aon ^| findstr :3000') do taskkill /F /PID %a"])
-                .output();
-        }
+    pub async fn hosted_repository(&self, full_name: &str, account_id: Option<&str>) -> Result<HostedRepository, AuthError> {
+        let (account, token) = self.account(account_id)?;
+        Ok(self.provider(&account.host).repository(&token, full_name).await?)
+    }
 
-        #[cfg(unix)]
-        {
-            let _ = std::process::Command::new("sh")
-                .arg("-c")
-                .arg("lsof -ti:3000 | xargs kill -9")
-                .output();
+    pub async fn list_pull_requests(
+        &self,
+        full_name: &str,
+        state: PullRequestState,
+        account_id: Option<&str>,
+    ) -> Result<Vec<PullRequest>, AuthError> {
+        let (account, token) = self.account(account_id)?;
+        Ok(self.provider(&account.host).list_pull_requests(&token, full_name, state).await?)
+    }
+
+    // Adds the public key to the account on its host, so pushes over SSH
+    // can use it
+    pub async fn add_ssh_key(&self, title: &str, public_key: &str, account_id: Option<&str>) -> Result<(), AuthError> {
+        let (account, token) = self.account(account_id)?;
+        let provider = self.provider(&account.host);
+        // Accounts signed in before the scope was requested don't have it
+        if let Some(scope) = provider.ssh_key_scope() {
+            if !account.scopes.iter().any(|granted| granted == scope) {
+                return Err(AuthError::MissingScope { login: account.login, host: account.host.web_url, scope });
+            }
         }
+        Ok(provider.add_ssh_key(&token, title, public_key).await?)
     }
 
-    pub async fn get_repository_stats(&self, repo_full_name: &str) -> Result<RepositoryStats, AuthError> {
-        let token = {
-            let state = self.auth_state.lock().unwrap();
-            state.access_token
-                .clone()
-                .ok_or_else(|| AuthError::OAuth("Not authenticated".to_string()))?
-        };
+    // Stops a running login, which frees the callback port
+    pub fn cancel_auth(&self) {
+        self.is_authenticating.store(false, Ordering::SeqCst);
+    }
 
-        println!("Fetching stats with token: {}", token.chars().take(10).collect::<String>());
-        println!("For repository: {}", repo_full_name);
+    pub async fn get_repository_stats(&self, repo_full_name: &str, account_id: Option<&str>) -> Result<RepositoryStats, AuthError> {
+        let (account, token) = self.account(account_id)?;
 
         if !repo_full_name.contains('/') {
             return Err(AuthError::OAuth(format!(
@@ -272,80 +594,463 @@ impl GitHubAuth {
             )));
         }
 
-        let client = &self.client;
-        
-        let repo_url = format!("https://api.github.com/repos/{}", repo_full_name);
-        println!("Making request to: {}", repo_url);
-        
-        let repo_response = client
-            .get(&repo_url)
-            .header("Authorization", format!("Bearer {}", token))
-            .header("User-Agent", "simplegit")
-            .header("Accept", "application/vnd.github.v3+json")
-            .send()
-            .await?;
+        let provider = self.provider(&account.host);
+        let repo = provider.repository(&token, repo_full_name).await?;
+        let contributors = provider.contributor_count(&token, repo_full_name).await?;
 
-        println!("Response status: {}", repo_response.status());
-        
-        if !repo_response.status().is_success() {
-            let error_text = repo_response.text().await?;
-            println!("Error response: {}", error_text);
-            return Err(AuthError::OAuth(format!("GitHub API error: {}", error_text)));
+        // Hosts don't report commit or branch counts without walking the
+        // repository, so the size stands in for the former
+        Ok(RepositoryStats {
+            commits: if repo.default_branch.is_some() { repo.size as usize } else { 0 },
+            branches: 1,
+            contributors: contributors.unwrap_or(0),
+        })
+    }
+
+    // Whether the host still accepts the token. Other failures are errors so
+    // that an unreachable host doesn't look like a revoked token.
+    pub async fn validate_token(&self, token: &str, host: &Host) -> Result<bool, AuthError> {
+        match self.provider(host).current_user(token).await {
+            Ok(_) => Ok(true),
+            Err(HostingError::Unauthorized { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
         }
+    }
+}
 
-        let repo_response = repo_response.json::<serde_json::Value>().await?;
-        println!("Repo response: {}", serde_json::to_string_pretty(&repo_response).unwrap());
+// A request that reached the loopback listener
+#[derive(Debug, PartialEq)]
+enum CallbackRequest {
+    Code { code: String, state: String },
+    // The user declined, or GitHub reported an error
+    Denied { reason: String, state: String },
+    // Favicon requests and anything else that isn't the redirect
+    Unrelated,
+}
 
-        let contributors_url = format!("https://api.github.com/repos/{}/contributors?per_page=1", repo_full_name);
-        let contributors_response = client
-            .get(&contributors_url)
-            .header("Authorization", format!("Bearer {}", token))
-            .header("User-Agent", "simplegit")
-            .send()
-            .await?;
-        
-        let contributors = if let Some(count) = contributors_response.headers().get("link") {
-            let link = count.to_str().unwrap_or("");
-            if let Some(last_page) = link.split(",").find(|&l| l.contains("rel=\"last\"")) {
-                if let Some(page_num) = last_page.split("&page=").nth(1) {
-                    page_num.split(">").next().unwrap_or("0").parse().unwrap_or(0)
-                } else {
-                    0
-                }
-            } else {
-                1
+fn parse_callback(target: &str) -> CallbackRequest {
+    let url = match oauth2::url::Url::parse("http://localhost").and_then(|base| base.join(target)) {
+        Ok(url) => url,
+        Err(_) => return CallbackRequest::Unrelated,
+    };
+    if url.path() != "/callback" {
+        return CallbackRequest::Unrelated;
+    }
+
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let state = match param("state") {
+        Some(state) => state,
+        None => return CallbackRequest::Unrelated,
+    };
+    if let Some(error) = param("error") {
+        let reason = param("error_description").unwrap_or(error);
+        return CallbackRequest::Denied { reason, state };
+    }
+    match param("code") {
+        Some(code) => CallbackRequest::Code { code, state },
+        None => CallbackRequest::Unrelated,
+    }
+}
+
+// Reads the request head and returns the request target of a GET
+async fn read_request_target(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    // Drain the headers so closing the socket doesn't reset the connection
+    // before the browser has read the response
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Ok(target.to_string()),
+        _ => Ok(String::new()),
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let body = format!(
+        "<html><body><h1>SimpleGit</h1><p>{}</p></body></html>",
+        message.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::{CredentialStore, EncryptedFileStore, GITHUB_TOKEN_KEY};
+    use crate::hosting::HostedUser;
+    use crate::test_support::{account_registry, StubResponse, StubServer};
+    use serde_json::json;
+    use std::path::Path;
+    use std::sync::atomic::AtomicUsize;
+
+    // Signs in against a GitHub Enterprise stand-in at the stub's URL
+    fn auth_for(server: &StubServer, dir: &Path) -> HostAuth {
+        let mut settings = Settings::default();
+        settings.github_enterprise.push(OAuthAppSettings {
+            host: Some(server.url.clone()),
+            client_id: Some("client-id".into()),
+            ..OAuthAppSettings::default()
+        });
+        let auth = HostAuth::new(&settings, account_registry(dir)).unwrap();
+        auth.is_authenticating.store(true, Ordering::SeqCst);
+        auth
+    }
+
+    fn device(expires_in: u64) -> DeviceAuthorization {
+        DeviceAuthorization {
+            device_code: "device-code".into(),
+            user_code: "ABCD-1234".into(),
+            verification_uri: "https://example.com/login/device".into(),
+            expires_in,
+            interval: 1,
+        }
+    }
+
+    // Answers token polls with `responses` in turn, then with a token
+    async fn token_server(responses: Vec<serde_json::Value>) -> StubServer {
+        let polls = AtomicUsize::new(0);
+        StubServer::start(move |_| {
+            let poll = polls.fetch_add(1, Ordering::SeqCst);
+            let body = responses.get(poll).cloned().unwrap_or_else(|| json!({ "access_token": "token" }));
+            StubResponse::json(200, body)
+        }).await
+    }
+
+    fn pending() -> serde_json::Value {
+        json!({ "error": "authorization_pending" })
+    }
+
+    async fn poll(server: &StubServer, expires_in: u64) -> Result<String, AuthError> {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth_for(server, dir.path());
+        auth.poll_device_token(ProviderKind::GitHub, Some(&server.url), &device(expires_in)).await
+    }
+
+    #[tokio::test]
+    async fn requests_a_device_code_with_the_host_scopes() {
+        let server = StubServer::start(|_| StubResponse::json(200, json!({
+            "device_code": "device-code",
+            "user_code": "ABCD-1234",
+            "verification_uri": "https://example.com/login/device",
+            "expires_in": 900,
+        }))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth_for(&server, dir.path());
+
+        let device = auth.request_device_code(ProviderKind::GitHub, Some(&server.url)).await.unwrap();
+        assert_eq!(device.user_code, "ABCD-1234");
+        assert_eq!(device.interval, 5);
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path(), "/login/device/code");
+        assert_eq!(request.form("client_id").as_deref(), Some("client-id"));
+        assert_eq!(request.form("scope").as_deref(), Some("repo user write:public_key"));
+    }
+
+    #[tokio::test]
+    async fn device_code_errors_are_reported() {
+        let server = StubServer::start(|_| StubResponse::json(400, json!({
+            "error": "unauthorized_client",
+            "error_description": "Device flow is disabled",
+        }))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth_for(&server, dir.path());
+
+        let error = auth.request_device_code(ProviderKind::GitHub, Some(&server.url)).await.unwrap_err();
+        assert!(error.to_string().contains("Device flow is disabled"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polls_until_the_code_is_approved() {
+        let server = token_server(vec![pending(), pending()]).await;
+        assert_eq!(poll(&server, 900).await.unwrap(), "token");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].path(), "/login/oauth/access_token");
+        assert_eq!(requests[0].form("device_code").as_deref(), Some("device-code"));
+        assert_eq!(requests[0].form("grant_type").as_deref(), Some(DEVICE_CODE_GRANT_TYPE));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_down_adds_five_seconds_to_the_interval() {
+        let server = token_server(vec![json!({ "error": "slow_down" }), pending()]).await;
+        poll(&server, 900).await.unwrap();
+
+        let requests = server.requests();
+        let gaps: Vec<u64> = requests.windows(2)
+            .map(|pair| (pair[1].received_at - pair[0].received_at).as_secs())
+            .collect();
+        assert_eq!(gaps, vec![6, 6]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn access_denied_ends_the_login() {
+        let server = token_server(vec![json!({ "error": "access_denied" })]).await;
+        let error = poll(&server, 900).await.unwrap_err();
+        assert_eq!(error.to_string(), "OAuth error: Access was denied");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_expired_code_times_out() {
+        let server = token_server(vec![pending(), json!({ "error": "expired_token" })]).await;
+        assert!(matches!(poll(&server, 900).await, Err(AuthError::TimedOut)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_polling_at_the_deadline() {
+        let server = token_server(vec![pending(); 100]).await;
+        assert!(matches!(poll(&server, 3).await, Err(AuthError::TimedOut)));
+        // Polls at 1s and 2s; the one due at 3s is past the deadline
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_stops_polling() {
+        // The stub cancels while answering the second poll, as the UI would
+        // between polls
+        let authenticating = Arc::new(AtomicBool::new(true));
+        let polls = AtomicUsize::new(0);
+        let cancel = authenticating.clone();
+        let server = StubServer::start(move |_| {
+            if polls.fetch_add(1, Ordering::SeqCst) == 1 {
+                cancel.store(false, Ordering::SeqCst);
             }
-        } else {
-            0
+            StubResponse::json(200, pending())
+        }).await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut auth = auth_for(&server, dir.path());
+        auth.is_authenticating = authenticating;
+
+        let result = auth.poll_device_token(ProviderKind::GitHub, Some(&server.url), &device(900)).await;
+        assert!(matches!(result, Err(AuthError::Cancelled)));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn parses_the_oauth_callback() {
+        assert_eq!(
+            parse_callback("/callback?code=abc&state=xyz"),
+            CallbackRequest::Code { code: "abc".into(), state: "xyz".into() }
+        );
+        assert_eq!(
+            parse_callback("/callback?error=access_denied&error_description=The+user+declined&state=xyz"),
+            CallbackRequest::Denied { reason: "The user declined".into(), state: "xyz".into() }
+        );
+        assert_eq!(
+            parse_callback("/callback?error=access_denied&state=xyz"),
+            CallbackRequest::Denied { reason: "access_denied".into(), state: "xyz".into() }
+        );
+        // The state is compared by the caller; a mismatch still parses
+        assert_eq!(
+            parse_callback("/callback?code=abc&state=forged"),
+            CallbackRequest::Code { code: "abc".into(), state: "forged".into() }
+        );
+        assert_eq!(parse_callback("/callback?code=abc"), CallbackRequest::Unrelated);
+        assert_eq!(parse_callback("/favicon.ico"), CallbackRequest::Unrelated);
+        assert_eq!(parse_callback("/callback?state=xyz"), CallbackRequest::Unrelated);
+        assert_eq!(parse_callback(""), CallbackRequest::Unrelated);
+    }
+
+    #[tokio::test]
+    async fn browser_login_exchanges_the_code_with_the_pkce_verifier() {
+        let server = StubServer::start(|request| match request.path() {
+            "/login/oauth/access_token" => StubResponse::json(200, json!({
+                "access_token": "token",
+                "token_type": "bearer",
+            })),
+            "/api/v3/user" => StubResponse::json(200, json!({ "id": 1, "login": "octocat" })),
+            _ => StubResponse::empty(404),
+        }).await;
+        let mut settings = Settings::default();
+        settings.github_enterprise.push(OAuthAppSettings {
+            host: Some(server.url.clone()),
+            client_id: Some("client-id".into()),
+            client_secret: Some("client-secret".into()),
+            ..OAuthAppSettings::default()
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let auth = HostAuth::new(&settings, account_registry(dir.path())).unwrap();
+        let app = auth.app(ProviderKind::GitHub, Some(&server.url)).unwrap();
+
+        // Plays the browser: an idle preconnected socket, a forged callback,
+        // a favicon request, then the real redirect
+        let challenge = Arc::new(PLMutex::new(String::new()));
+        let sent_challenge = challenge.clone();
+        let (statuses_tx, statuses) = tokio::sync::oneshot::channel::<Vec<u16>>();
+        let open_browser = move |auth_url: &str| {
+            let url = oauth2::url::Url::parse(auth_url).unwrap();
+            let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).unwrap().1.into_owned();
+            *sent_challenge.lock() = param("code_challenge");
+            let state = param("state");
+            let callback = param("redirect_uri").replace("localhost", "127.0.0.1");
+            tokio::spawn(async move {
+                let port = oauth2::url::Url::parse(&callback).unwrap().port().unwrap();
+                let _idle = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+                let client = reqwest::Client::new();
+                let mut statuses = Vec::new();
+                for url in [
+                    format!("{}?code=forged&state=wrong", callback),
+                    callback.replace("/callback", "/favicon.ico"),
+                    format!("{}?code=the-code&state={}", callback, state),
+                ] {
+                    statuses.push(client.get(url).send().await.unwrap().status().as_u16());
+                }
+                let _ = statuses_tx.send(statuses);
+            });
+            Ok(())
         };
 
-        Ok(RepositoryStats {
-            commits: repo_response["default_branch"].as_str()
-                .and_then(|_branch| repo_response["commits_url"].as_str())
-                .map(|_| repo_response["size"].as_u64().unwrap_or(0) as usize)
-                .unwrap_or(0),
-            branches: repo_response["branches_url"].as_str()
-                .map(|_| 1)
-                .unwrap_or(0),
-            contributors,
-        })
+        let started = Instant::now();
+        let account = auth.browser_login(app, open_browser).await.unwrap();
+        assert!(started.elapsed() < REQUEST_READ_TIMEOUT, "the idle socket held up the callback");
+        assert_eq!(account.login, "octocat");
+        assert_eq!(statuses.await.unwrap(), vec![400, 404, 200]);
+        assert!(!auth.is_authenticating.load(Ordering::SeqCst));
+
+        let requests = server.requests();
+        let token_request = requests.iter().find(|request| request.path() == "/login/oauth/access_token").unwrap();
+        assert_eq!(token_request.form("code").as_deref(), Some("the-code"));
+        assert_eq!(token_request.form("grant_type").as_deref(), Some("authorization_code"));
+        let verifier = PkceCodeVerifier::new(token_request.form("code_verifier").unwrap());
+        assert_eq!(PkceCodeChallenge::from_code_verifier_sha256(&verifier).as_str(), challenge.lock().as_str());
+        assert_eq!(auth.account(None).unwrap().1, "token");
+    }
+
+    // Signs in against a github.com stand-in at the stub's URL with a token
+    // left over from before accounts existed
+    async fn migrate(status: u16) -> (HostAuth, tempfile::TempDir) {
+        let server = StubServer::start(move |request| match (status, request.path()) {
+            (200, "/user") => StubResponse::json(200, json!({ "id": 1, "login": "octocat" })),
+            (200, _) => StubResponse::empty(404),
+            (status, _) => StubResponse::json(status, json!({ "message": "error" })),
+        }).await;
+        let dir = tempfile::tempdir().unwrap();
+        EncryptedFileStore::with_key(dir.path().join("credentials.vault"), [7; 32])
+            .set(GITHUB_TOKEN_KEY, "legacy-token")
+            .unwrap();
+        let settings = Settings {
+            github: OAuthAppSettings {
+                api_url: Some(server.url.clone()),
+                client_id: Some("client-id".into()),
+                ..OAuthAppSettings::default()
+            },
+            ..Settings::default()
+        };
+        let auth = HostAuth::new(&settings, account_registry(dir.path())).unwrap();
+
+        auth.migrate_legacy_token().await.unwrap();
+        (auth, dir)
     }
 
-    pub async fn validate_token(&self, token: &str) -> Result<bool, AuthError> {
-        if let Ok(mut state) = self.auth_state.lock() {
-            state.access_token = Some(token.to_string());
+    #[tokio::test]
+    async fn migrates_the_legacy_token_into_an_account() {
+        let (auth, _dir) = migrate(200).await;
+
+        let (account, token) = auth.account(None).unwrap();
+        assert_eq!(account.login, "octocat");
+        assert_eq!(token, "legacy-token");
+        assert_eq!(auth.accounts.lock().legacy_token().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn drops_the_legacy_token_only_when_github_rejects_it() {
+        let (auth, _dir) = migrate(401).await;
+        assert_eq!(auth.accounts.lock().legacy_token().unwrap(), None);
+        assert!(auth.accounts.lock().is_empty());
+
+        for status in [500, 502, 503] {
+            let (auth, _dir) = migrate(status).await;
+            assert_eq!(auth.accounts.lock().legacy_token().unwrap().as_deref(), Some("legacy-token"), "{}", status);
+            assert!(auth.accounts.lock().is_empty());
         }
-        
-        // Try to make a test API call to validate the token
-        let response = self.client
-            .get("https://api.github.com/user")
-            .header(
-                "Authorization",
-                format!("Bearer {}", token),
-            )
-            .send()
-            .await?;
-            
-        Ok(response.status().is_success())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn uploads_ssh_keys_only_with_the_write_public_key_scope() {
+        let server = StubServer::start(|_| StubResponse::json(201, json!({ "id": 1 }))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let auth = auth_for(&server, dir.path());
+        let host = auth.host(ProviderKind::GitHub, Some(&server.url));
+        let user = |id: u64, scopes: &[&str]| HostedUser {
+            id,
+            login: format!("user-{}", id),
+            name: None,
+            avatar_url: None,
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        };
+        let (old, new) = {
+            let mut accounts = auth.accounts.lock();
+            let old = accounts.add(user(1, &["repo", "user"]), host.clone(), "old-token").unwrap();
+            let new = accounts.add(user(2, &["repo", "user", "write:public_key"]), host, "new-token").unwrap();
+            (old, new)
+        };
+
+        let error = auth.add_ssh_key("laptop", "ssh-ed25519 AAAA", Some(&old.id)).await.unwrap_err();
+        assert!(matches!(error, AuthError::MissingScope { scope: "write:public_key", .. }), "{}", error);
+        assert!(error.to_string().contains("re-authenticate to grant write:public_key"), "{}", error);
+        assert!(server.requests().is_empty());
+
+        auth.add_ssh_key("laptop", "ssh-ed25519 AAAA", Some(&new.id)).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path(), "/api/v3/user/keys");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer new-token"));
+    }
+
+    #[test]
+    fn skips_invalid_oauth_apps() {
+        let app = |host: &str, client_id: Option<&str>| OAuthAppSettings {
+            host: Some(host.into()),
+            client_id: client_id.map(Into::into),
+            ..OAuthAppSettings::default()
+        };
+        let settings = Settings {
+            github_enterprise: vec![app("not a host", Some("client-id"))],
+            gitlab: vec![app("gitlab.example.com", None)],
+            gitea: vec![app("git.example.com", Some("client-id"))],
+            ..Settings::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+
+        let auth = HostAuth::new(&settings, account_registry(dir.path())).unwrap();
+
+        assert!(auth.app(ProviderKind::Gitea, Some("git.example.com")).is_ok());
+        assert!(auth.app(ProviderKind::GitHub, Some("not a host")).is_err());
+        assert!(auth.app(ProviderKind::GitLab, Some("gitlab.example.com")).is_err());
+        assert_eq!(auth.apps.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gitea_has_no_device_login() {
+        let mut settings = Settings::default();
+        settings.gitea.push(OAuthAppSettings {
+            host: Some("git.example.com".into()),
+            client_id: Some("client-id".into()),
+            ..OAuthAppSettings::default()
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let auth = HostAuth::new(&settings, account_registry(dir.path())).unwrap();
+
+        let error = auth.request_device_code(ProviderKind::Gitea, Some("git.example.com")).await.unwrap_err();
+        assert!(error.to_string().contains("has no device login"), "{}", error);
+    }
+}
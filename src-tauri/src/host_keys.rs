@@ -0,0 +1,370 @@
+use git2::cert::Cert;
+use git2::{CertificateCheckStatus, ErrorClass, ErrorCode};
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use ssh_key::known_hosts::{HostPatterns, KnownHosts, Marker};
+use ssh_key::{HashAlg, PublicKey};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::git_commands::GitError;
+use crate::ssh_keys;
+
+// Asks the user whether to trust the key of a host seen for the first time
+pub type HostKeyPrompt = Arc<dyn Fn(&UnknownHostKey) -> bool + Send + Sync>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnknownHostKey {
+    pub host: String,
+    // e.g. ssh-ed25519
+    pub algorithm: String,
+    // SHA256:..., as printed by ssh
+    pub fingerprint: String,
+}
+
+// A TLS certificate that was offered by a host, kept to explain a failed
+// validation
+#[derive(Debug, Clone)]
+pub struct TlsCertificate {
+    pub host: String,
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    Known,
+    Unknown,
+    // The host is listed with another key of the same type
+    Changed,
+    Revoked,
+}
+
+// How a network operation decides whether to trust the server
+#[derive(Clone, Default)]
+pub struct HostVerification {
+    // Hostname -> SHA-256 fingerprint of a TLS certificate to accept even
+    // though it doesn't validate
+    pub trusted_certificates: BTreeMap<String, String>,
+    pub host_key_prompt: Option<HostKeyPrompt>,
+    // The last TLS certificate seen, shared with the caller's copies
+    pub last_certificate: Arc<Mutex<Option<TlsCertificate>>>,
+}
+
+impl HostVerification {
+    // SSH host keys must be in known_hosts or be accepted by the user, who
+    // then gets them added there. TLS certificates go through the normal
+    // validation unless their host has a trusted fingerprint that matches.
+    // libgit2 only passes the hostname, so the port comes from the remote URL.
+    pub fn check(&self, cert: &Cert<'_>, host: &str, port: Option<u16>) -> Result<CertificateCheckStatus, git2::Error> {
+        let host = host.to_lowercase();
+        if let Some(hostkey) = cert.as_hostkey() {
+            // libssh2 builds without the raw key are left to libgit2's own
+            // known_hosts check
+            let key = match hostkey.hostkey().and_then(|bytes| PublicKey::from_bytes(bytes).ok()) {
+                Some(key) => key,
+                None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+            };
+            return self.check_host_key(&known_hosts_name(&host, port), &key);
+        }
+        if let Some(x509) = cert.as_x509() {
+            let fingerprint = certificate_fingerprint(x509.data());
+            let trusted = self.trusted_certificates.get(&host)
+                .is_some_and(|pinned| same_fingerprint(pinned, &fingerprint));
+            *self.last_certificate.lock() = Some(TlsCertificate { host, fingerprint });
+            if trusted {
+                return Ok(CertificateCheckStatus::CertificateOk);
+            }
+        }
+        Ok(CertificateCheckStatus::CertificatePassthrough)
+    }
+
+    fn check_host_key(&self, host: &str, key: &PublicKey) -> Result<CertificateCheckStatus, git2::Error> {
+        let known_hosts = known_hosts_path();
+        let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+        let message = match host_key_status(&known_hosts, host, key) {
+            HostKeyStatus::Known => return Ok(CertificateCheckStatus::CertificateOk),
+            HostKeyStatus::Revoked => format!(
+                "The host key of {} ({}) is marked as revoked in {}",
+                host, fingerprint, known_hosts.display()
+            ),
+            HostKeyStatus::Changed => format!(
+                "The host key of {} has changed to {}. Someone could be intercepting the connection. \
+                 If the server's key was replaced on purpose, remove its old entry from {}.",
+                host, fingerprint, known_hosts.display()
+            ),
+            HostKeyStatus::Unknown => {
+                let unknown = UnknownHostKey {
+                    host: host.to_string(),
+                    algorithm: key.algorithm().to_string(),
+                    fingerprint: fingerprint.clone(),
+                };
+                if self.host_key_prompt.as_ref().is_some_and(|prompt| prompt(&unknown)) {
+                    add_known_host(&known_hosts, host, key)
+                        .map_err(|e| git2::Error::from_str(&e.to_string()))?;
+                    return Ok(CertificateCheckStatus::CertificateOk);
+                }
+                format!("The host key of {} ({}) is not known and was not accepted", host, fingerprint)
+            }
+        };
+        Err(git2::Error::new(ErrorCode::Certificate, ErrorClass::Ssh, message))
+    }
+
+    // Adds the certificate's fingerprint to a rejected TLS connection so
+    // that the user can decide whether to trust it
+    pub fn explain(&self, error: git2::Error) -> GitError {
+        if error.code() == ErrorCode::Certificate {
+            if let Some(cert) = self.last_certificate.lock().as_ref() {
+                return GitError::Custom(format!(
+                    "The TLS certificate of {} is not trusted (SHA-256 fingerprint {}). \
+                     If the server uses a self-signed certificate with this fingerprint, trust it for {} to connect.",
+                    cert.host, cert.fingerprint, cert.host
+                ));
+            }
+        }
+        GitError::Git(error)
+    }
+}
+
+pub fn known_hosts_path() -> PathBuf {
+    ssh_keys::ssh_dir().join("known_hosts")
+}
+
+// How ssh names a host in known_hosts: "host" on port 22, "[host]:port" otherwise
+pub fn known_hosts_name(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) if port != 22 => format!("[{}]:{}", host, port),
+        _ => host.to_string(),
+    }
+}
+
+// Looks `host` up in a known_hosts file; a missing file knows no hosts.
+// Lines that don't parse are skipped, like ssh does.
+pub fn host_key_status(known_hosts: &Path, host: &str, key: &PublicKey) -> HostKeyStatus {
+    let content = match fs::read_to_string(known_hosts) {
+        Ok(content) => content,
+        Err(_) => return HostKeyStatus::Unknown,
+    };
+    let mut status = HostKeyStatus::Unknown;
+    for entry in KnownHosts::new(&content).filter_map(Result::ok) {
+        if !host_matches(entry.host_patterns(), host) {
+            continue;
+        }
+        let same_key = entry.public_key().key_data() == key.key_data();
+        match entry.marker() {
+            Some(Marker::Revoked) if same_key => return HostKeyStatus::Revoked,
+            Some(_) => {}
+            None if same_key => status = HostKeyStatus::Known,
+            None if entry.public_key().algorithm() == key.algorithm() && status != HostKeyStatus::Known => {
+                status = HostKeyStatus::Changed;
+            }
+            None => {}
+        }
+    }
+    status
+}
+
+// Appends "<host> <type> <key>", creating ~/.ssh and the file if needed
+pub fn add_known_host(known_hosts: &Path, host: &str, key: &PublicKey) -> Result<(), GitError> {
+    let line = PublicKey::from(key.key_data().clone()).to_openssh()
+        .map_err(|e| GitError::Custom(format!("Failed to encode the host key: {}", e)))?;
+    if let Some(dir) = known_hosts.parent() {
+        ssh_keys::create_ssh_dir(dir)?;
+    }
+    // Don't glue the new entry onto a last line without a newline
+    let needs_newline = fs::read(known_hosts)
+        .map(|content| content.last().is_some_and(|byte| *byte != b'\n'))
+        .unwrap_or(false);
+    let mut file = OpenOptions::new().create(true).append(true).open(known_hosts)?;
+    if needs_newline {
+        writeln!(file)?;
+    }
+    writeln!(file, "{} {}", host, line)?;
+    Ok(())
+}
+
+// Colon-separated hex, as printed by openssl x509 -fingerprint -sha256
+pub fn certificate_fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der).iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn same_fingerprint(a: &str, b: &str) -> bool {
+    let normalize = |fingerprint: &str| -> String {
+        fingerprint.chars()
+            .filter(|c| *c != ':' && !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    };
+    normalize(a) == normalize(b)
+}
+
+// `host` as given by known_hosts_name; "[host]:22" is the same as "host"
+fn host_matches(patterns: &HostPatterns, host: &str) -> bool {
+    match patterns {
+        HostPatterns::HashedName { salt, hash } => {
+            let mut mac = match Hmac::<Sha1>::new_from_slice(salt) {
+                Ok(mac) => mac,
+                Err(_) => return false,
+            };
+            mac.update(host.as_bytes());
+            mac.verify_slice(hash).is_ok()
+        }
+        HostPatterns::Patterns(patterns) => {
+            let mut matched = false;
+            for pattern in patterns {
+                let (negated, pattern) = match pattern.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern.as_str()),
+                };
+                let pattern = pattern.to_lowercase();
+                let pattern = pattern.strip_prefix('[')
+                    .and_then(|pattern| pattern.strip_suffix("]:22"))
+                    .unwrap_or(&pattern);
+                if wildcard_match(pattern.as_bytes(), host.as_bytes()) {
+                    // A matching negation rules the host out whatever else matches
+                    if negated {
+                        return false;
+                    }
+                    matched = true;
+                }
+            }
+            matched
+        }
+    }
+}
+
+// `*` matches any run of characters and `?` a single one
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| wildcard_match(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && wildcard_match(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && wildcard_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::OsRng;
+    use ssh_key::{Algorithm, PrivateKey};
+
+    fn random_key() -> PublicKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap().public_key().clone()
+    }
+
+    fn entry(host: &str, key: &PublicKey) -> String {
+        format!("{} {}\n", host, key.to_openssh().unwrap())
+    }
+
+    fn patterns(patterns: &[&str]) -> HostPatterns {
+        HostPatterns::Patterns(patterns.iter().map(|pattern| pattern.to_string()).collect())
+    }
+
+    #[test]
+    fn looks_hosts_up_in_known_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let known_hosts = dir.path().join("known_hosts");
+        let (key, other, revoked) = (random_key(), random_key(), random_key());
+        let content = [
+            "# comment\n".to_string(),
+            "not a valid entry\n".to_string(),
+            entry("github.com,140.82.121.4", &key),
+            entry("changed.example.com", &other),
+            format!("@revoked {}", entry("*", &revoked)),
+            entry("[git.example.com]:2222", &key),
+        ].concat();
+        fs::write(&known_hosts, content).unwrap();
+
+        assert_eq!(host_key_status(&known_hosts, "github.com", &key), HostKeyStatus::Known);
+        assert_eq!(host_key_status(&known_hosts, "gitlab.com", &key), HostKeyStatus::Unknown);
+        assert_eq!(host_key_status(&known_hosts, "changed.example.com", &key), HostKeyStatus::Changed);
+        assert_eq!(host_key_status(&known_hosts, "github.com", &revoked), HostKeyStatus::Revoked);
+        assert_eq!(host_key_status(&known_hosts, "[git.example.com]:2222", &key), HostKeyStatus::Known);
+        assert_eq!(host_key_status(&known_hosts, "git.example.com", &key), HostKeyStatus::Unknown);
+        assert_eq!(host_key_status(&dir.path().join("missing"), "github.com", &key), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn names_hosts_on_other_ports_with_brackets() {
+        assert_eq!(known_hosts_name("host", None), "host");
+        assert_eq!(known_hosts_name("host", Some(22)), "host");
+        assert_eq!(known_hosts_name("host", Some(2222)), "[host]:2222");
+    }
+
+    #[test]
+    fn matches_host_patterns() {
+        assert!(host_matches(&patterns(&["GitHub.com"]), "github.com"));
+        assert!(host_matches(&patterns(&["[github.com]:22"]), "github.com"));
+        assert!(host_matches(&patterns(&["[git.example.com]:2222"]), "[git.example.com]:2222"));
+        assert!(!host_matches(&patterns(&["[git.example.com]:2222"]), "git.example.com"));
+        assert!(host_matches(&patterns(&["*.example.com"]), "git.example.com"));
+        assert!(!host_matches(&patterns(&["*.example.com"]), "example.com"));
+        // A negation wins whatever its position
+        assert!(!host_matches(&patterns(&["*.example.com", "!secret.example.com"]), "secret.example.com"));
+        assert!(!host_matches(&patterns(&["!secret.example.com", "*.example.com"]), "secret.example.com"));
+        assert!(host_matches(&patterns(&["!secret.example.com", "*.example.com"]), "git.example.com"));
+    }
+
+    #[test]
+    fn matches_hashed_hosts() {
+        let salt = b"0123456789abcdefghij".to_vec();
+        let mut mac = Hmac::<Sha1>::new_from_slice(&salt).unwrap();
+        mac.update(b"[git.example.com]:2222");
+        let hash: [u8; 20] = mac.finalize().into_bytes().into();
+        let hashed = HostPatterns::HashedName { salt, hash };
+
+        assert!(host_matches(&hashed, "[git.example.com]:2222"));
+        assert!(!host_matches(&hashed, "git.example.com"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match(b"*", b""));
+        assert!(wildcard_match(b"git*.example.com", b"git.example.com"));
+        assert!(wildcard_match(b"git?.example.com", b"git1.example.com"));
+        assert!(!wildcard_match(b"git?.example.com", b"git.example.com"));
+        assert!(wildcard_match(b"*.*.com", b"a.b.com"));
+        assert!(!wildcard_match(b"example.com", b"example.co"));
+    }
+
+    #[test]
+    fn appends_entries_on_their_own_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let known_hosts = dir.path().join("ssh").join("known_hosts");
+        let (first, second) = (random_key(), random_key());
+
+        add_known_host(&known_hosts, "github.com", &first).unwrap();
+        assert_eq!(host_key_status(&known_hosts, "github.com", &first), HostKeyStatus::Known);
+
+        // A file whose last line has no newline
+        let content = fs::read_to_string(&known_hosts).unwrap();
+        fs::write(&known_hosts, content.trim_end()).unwrap();
+        add_known_host(&known_hosts, "[git.example.com]:2222", &second).unwrap();
+
+        let content = fs::read_to_string(&known_hosts).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("[git.example.com]:2222 ssh-ed25519 "));
+        assert!(content.ends_with('\n'));
+        assert_eq!(host_key_status(&known_hosts, "github.com", &first), HostKeyStatus::Known);
+        assert_eq!(host_key_status(&known_hosts, "[git.example.com]:2222", &second), HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn compares_fingerprints_loosely() {
+        let fingerprint = certificate_fingerprint(b"certificate");
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        assert!(same_fingerprint(&fingerprint, &fingerprint.replace(':', "").to_lowercase()));
+        assert!(same_fingerprint("ab:cd", " AB CD "));
+        assert!(!same_fingerprint("ab:cd", "ab:ce"));
+    }
+}
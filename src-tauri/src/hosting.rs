@@ -0,0 +1,391 @@
+mod gitea;
+mod github;
+mod gitlab;
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+
+pub use gitea::GiteaProvider;
+pub use github::GitHubProvider;
+pub use gitlab::GitLabProvider;
+
+// Upper bound on the pages a listing fetches
+const MAX_PAGES: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    GitHub,
+    GitLab,
+    // Also covers Forgejo, which keeps Gitea's API
+    Gitea,
+}
+
+impl ProviderKind {
+    fn default_web_url(self) -> &'static str {
+        match self {
+            ProviderKind::GitHub => "https://github.com",
+            ProviderKind::GitLab => "https://gitlab.com",
+            ProviderKind::Gitea => "https://gitea.com",
+        }
+    }
+}
+
+// One instance of a hosting service: github.com, a GitHub Enterprise Server,
+// gitlab.com or a self-managed GitLab, a Gitea/Forgejo server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Host {
+    // Hosts saved before other providers existed are GitHub ones
+    #[serde(default)]
+    pub provider: ProviderKind,
+    // e.g. https://git.example.com, without a trailing slash
+    pub web_url: String,
+    pub api_url: String,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Host::new(ProviderKind::GitHub, None, None)
+    }
+}
+
+impl Host {
+    // `host` is a hostname or base URL, None meaning the provider's public
+    // instance. The API base is derived from it unless `api_url` overrides it.
+    pub fn new(provider: ProviderKind, host: Option<&str>, api_url: Option<&str>) -> Self {
+        let web_url = match non_empty(host) {
+            Some(host) if host.contains("://") => host.trim_end_matches('/').to_string(),
+            Some(host) => format!("https://{}", host.trim_end_matches('/')),
+            None => provider.default_web_url().to_string(),
+        };
+        let api_url = match non_empty(api_url) {
+            Some(api_url) => api_url.trim_end_matches('/').to_string(),
+            None => match provider {
+                ProviderKind::GitHub if web_url == "https://github.com" => "https://api.github.com".to_string(),
+                ProviderKind::GitHub => format!("{}/api/v3", web_url),
+                ProviderKind::GitLab => format!("{}/api/v4", web_url),
+                ProviderKind::Gitea => format!("{}/api/v1", web_url),
+            },
+        };
+        Host { provider, web_url, api_url }
+    }
+
+    pub fn api(&self, path: &str) -> String {
+        format!("{}{}", self.api_url, path)
+    }
+
+    // Talks to this host's API
+    pub fn provider(&self, client: reqwest::Client) -> Box<dyn HostingProvider> {
+        match self.provider {
+            ProviderKind::GitHub => Box::new(GitHubProvider::new(self.clone(), client)),
+            ProviderKind::GitLab => Box::new(GitLabProvider::new(self.clone(), client)),
+            ProviderKind::Gitea => Box::new(GiteaProvider::new(self.clone(), client)),
+        }
+    }
+}
+
+// OAuth endpoints of a host, served by its web URL
+#[derive(Debug, Clone)]
+pub struct OAuthEndpoints {
+    pub authorize_url: String,
+    pub token_url: String,
+    // None if the host has no device authorization flow
+    pub device_code_url: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HostingError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("{host} rejected the token")]
+    Unauthorized { host: String },
+    #[error("{host} returned {status}: {message}")]
+    Api { host: String, status: StatusCode, message: String },
+}
+
+// The owner of a token
+#[derive(Debug, Clone)]
+pub struct HostedUser {
+    // The host's numeric user id
+    pub id: u64,
+    pub login: String,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    // Scopes granted to the token, where the host reports them
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostedRepository {
+    // owner/name, or group/subgroup/name on GitLab
+    pub full_name: String,
+    pub description: Option<String>,
+    pub default_branch: Option<String>,
+    pub private: bool,
+    pub web_url: String,
+    pub clone_url: String,
+    // Repository size in KB as reported by the host
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PullRequestState {
+    #[default]
+    Open,
+    Closed,
+    Merged,
+    All,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PullRequest {
+    // Per-repository number (GitLab's iid)
+    pub number: u64,
+    pub title: String,
+    pub state: PullRequestState,
+    pub author: String,
+    pub source_branch: String,
+    pub target_branch: String,
+    pub draft: bool,
+    pub web_url: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+// Everything the app needs from a hosting service: how to sign in to it and
+// its API. Tokens are passed per call since one provider serves every
+// account on its host.
+#[async_trait]
+pub trait HostingProvider: Send + Sync {
+    // Where the standard authorization code and device flows (RFC 6749,
+    // RFC 8628) take place; HostAuth runs them against these
+    fn oauth_endpoints(&self) -> OAuthEndpoints;
+
+    // Scopes requested at sign-in: enough to list, clone and push repositories
+    fn oauth_scopes(&self) -> &'static [&'static str];
+
+    // Extra query parameters for the authorization URL
+    fn authorize_params(&self) -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+
+    // Scope a token needs for add_ssh_key, on hosts that report scopes
+    fn ssh_key_scope(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Who the token belongs to; fails with Unauthorized for a rejected token
+    async fn current_user(&self, token: &str) -> Result<HostedUser, HostingError>;
+
+    async fn list_repositories(&self, token: &str) -> Result<Vec<HostedRepository>, HostingError>;
+
+    async fn repository(&self, token: &str, full_name: &str) -> Result<HostedRepository, HostingError>;
+
+    // None where the host doesn't report it
+    async fn contributor_count(&self, token: &str, full_name: &str) -> Result<Option<usize>, HostingError>;
+
+    async fn list_pull_requests(
+        &self,
+        token: &str,
+        full_name: &str,
+        state: PullRequestState,
+    ) -> Result<Vec<PullRequest>, HostingError>;
+
+    // Adds an SSH public key (one OpenSSH line) to the token owner's account
+    async fn add_ssh_key(&self, token: &str, title: &str, public_key: &str) -> Result<(), HostingError>;
+}
+
+// Request body of POST /user/keys, which all three APIs share
+#[derive(Serialize)]
+struct NewSshKey<'a> {
+    title: &'a str,
+    key: &'a str,
+}
+
+async fn get(
+    client: &reqwest::Client,
+    host: &Host,
+    token: &str,
+    path: &str,
+) -> Result<reqwest::Response, HostingError> {
+    send(host, client.get(host.api(path)), token).await
+}
+
+async fn post<T: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    host: &Host,
+    token: &str,
+    path: &str,
+    body: &T,
+) -> Result<reqwest::Response, HostingError> {
+    send(host, client.post(host.api(path)).json(body), token).await
+}
+
+// Sends an authenticated request and turns error statuses into HostingError
+async fn send(host: &Host, request: reqwest::RequestBuilder, token: &str) -> Result<reqwest::Response, HostingError> {
+    let response = request.bearer_auth(token).send().await?;
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(HostingError::Unauthorized { host: host.web_url.clone() });
+    }
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(HostingError::Api { host: host.web_url.clone(), status, message });
+    }
+    Ok(response)
+}
+
+// GETs a paginated listing page by page until a short page or MAX_PAGES.
+// `per_page_param` is "per_page" on GitHub and GitLab, "limit" on Gitea.
+async fn get_pages<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    host: &Host,
+    token: &str,
+    path: &str,
+    per_page_param: &str,
+    per_page: usize,
+) -> Result<Vec<T>, HostingError> {
+    let separator = if path.contains('?') { '&' } else { '?' };
+    let mut items = Vec::new();
+    for page in 1..=MAX_PAGES {
+        let page_path = format!("{}{}{}={}&page={}", path, separator, per_page_param, per_page, page);
+        let batch = get(client, host, token, &page_path).await?.json::<Vec<T>>().await?;
+        let last = batch.len() < per_page;
+        items.extend(batch);
+        if last {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{StubResponse, StubServer};
+    use serde_json::json;
+
+    fn stub_host(provider: ProviderKind, server: &StubServer) -> Host {
+        Host::new(provider, Some(&server.url), Some(&server.url))
+    }
+
+    // Answers every page with `per_page` numbers, or `last_page_len` of them
+    // on page `last_page`
+    async fn pages_server(per_page: usize, last_page: usize, last_page_len: usize) -> StubServer {
+        StubServer::start(move |request| {
+            let page: usize = request.query("page").unwrap().parse().unwrap();
+            let len = if page == last_page { last_page_len } else { per_page };
+            StubResponse::json(200, json!(vec![page; len]))
+        }).await
+    }
+
+    #[test]
+    fn derives_urls_from_the_host_name() {
+        let urls = |provider, host: Option<&str>, api_url: Option<&str>| {
+            let host = Host::new(provider, host, api_url);
+            (host.web_url, host.api_url)
+        };
+        let pair = |web_url: &str, api_url: &str| (web_url.to_string(), api_url.to_string());
+
+        assert_eq!(urls(ProviderKind::GitHub, None, None), pair("https://github.com", "https://api.github.com"));
+        assert_eq!(urls(ProviderKind::GitHub, Some("  "), None), pair("https://github.com", "https://api.github.com"));
+        assert_eq!(urls(ProviderKind::GitHub, Some("github.com"), None), pair("https://github.com", "https://api.github.com"));
+        assert_eq!(
+            urls(ProviderKind::GitHub, Some("github.example.com/"), None),
+            pair("https://github.example.com", "https://github.example.com/api/v3"),
+        );
+        assert_eq!(
+            urls(ProviderKind::GitHub, Some("http://ghe.local:8080/"), Some("http://ghe.local:8080/api/")),
+            pair("http://ghe.local:8080", "http://ghe.local:8080/api"),
+        );
+        assert_eq!(urls(ProviderKind::GitLab, None, None), pair("https://gitlab.com", "https://gitlab.com/api/v4"));
+        assert_eq!(
+            urls(ProviderKind::GitLab, Some("gitlab.example.com"), Some("")),
+            pair("https://gitlab.example.com", "https://gitlab.example.com/api/v4"),
+        );
+        assert_eq!(urls(ProviderKind::Gitea, None, None), pair("https://gitea.com", "https://gitea.com/api/v1"));
+        assert_eq!(
+            urls(ProviderKind::Gitea, Some("git.example.com"), None),
+            pair("https://git.example.com", "https://git.example.com/api/v1"),
+        );
+        assert_eq!(
+            Host::new(ProviderKind::Gitea, Some("git.example.com"), None).api("/user"),
+            "https://git.example.com/api/v1/user",
+        );
+    }
+
+    #[tokio::test]
+    async fn get_pages_stops_after_a_short_page() {
+        let server = pages_server(2, 3, 1).await;
+        let host = stub_host(ProviderKind::Gitea, &server);
+        let items: Vec<usize> = get_pages(&reqwest::Client::new(), &host, "token", "/items?sort=name", "limit", 2)
+            .await
+            .unwrap();
+
+        assert_eq!(items, vec![1, 1, 2, 2, 3]);
+        let targets: Vec<String> = server.requests().into_iter().map(|request| request.target).collect();
+        assert_eq!(targets, vec![
+            "/items?sort=name&limit=2&page=1",
+            "/items?sort=name&limit=2&page=2",
+            "/items?sort=name&limit=2&page=3",
+        ]);
+        assert_eq!(server.requests()[0].header("authorization"), Some("Bearer token"));
+    }
+
+    #[tokio::test]
+    async fn get_pages_stops_after_an_empty_page() {
+        let server = pages_server(2, 2, 0).await;
+        let host = stub_host(ProviderKind::GitHub, &server);
+        let items: Vec<usize> = get_pages(&reqwest::Client::new(), &host, "token", "/items", "per_page", 2)
+            .await
+            .unwrap();
+
+        assert_eq!(items, vec![1, 1]);
+        assert_eq!(server.requests()[0].target, "/items?per_page=2&page=1");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_pages_stops_at_max_pages() {
+        let server = pages_server(2, usize::MAX, 0).await;
+        let host = stub_host(ProviderKind::GitHub, &server);
+        let items: Vec<usize> = get_pages(&reqwest::Client::new(), &host, "token", "/items", "per_page", 2)
+            .await
+            .unwrap();
+
+        assert_eq!(items.len(), 2 * MAX_PAGES);
+        assert_eq!(server.requests().len(), MAX_PAGES);
+    }
+
+    #[tokio::test]
+    async fn maps_error_statuses() {
+        let server = StubServer::start(|request| match request.path() {
+            "/user" => StubResponse::json(401, json!({ "message": "Bad credentials" })),
+            _ => StubResponse::json(404, json!({ "message": "Not Found" })),
+        }).await;
+        let client = reqwest::Client::new();
+
+        for provider in [ProviderKind::GitHub, ProviderKind::GitLab, ProviderKind::Gitea] {
+            let host = stub_host(provider, &server);
+            match host.provider(client.clone()).current_user("revoked").await {
+                Err(HostingError::Unauthorized { host }) => assert_eq!(host, server.url),
+                other => panic!("expected Unauthorized, got {:?}", other),
+            }
+            match host.provider(client.clone()).repository("token", "owner/missing").await {
+                Err(HostingError::Api { status, message, .. }) => {
+                    assert_eq!(status, StatusCode::NOT_FOUND);
+                    assert!(message.contains("Not Found"));
+                }
+                other => panic!("expected Api, got {:?}", other),
+            }
+        }
+    }
+}
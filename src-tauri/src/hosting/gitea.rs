@@ -0,0 +1,264 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{get, get_pages, post, Host, HostedRepository, HostedUser, HostingError, HostingProvider, NewSshKey, OAuthEndpoints, PullRequest, PullRequestState};
+
+// Gitea's default MAX_RESPONSE_ITEMS; larger limits are capped to it anyway
+const PER_PAGE: usize = 50;
+
+// Gitea and Forgejo (REST API v1)
+pub struct GiteaProvider {
+    host: Host,
+    client: reqwest::Client,
+}
+
+impl GiteaProvider {
+    pub fn new(host: Host, client: reqwest::Client) -> Self {
+        GiteaProvider { host, client }
+    }
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: u64,
+    login: String,
+    // Empty rather than missing when unset
+    #[serde(default)]
+    full_name: String,
+    avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+    description: Option<String>,
+    default_branch: Option<String>,
+    private: bool,
+    html_url: String,
+    clone_url: String,
+    #[serde(default)]
+    size: u64,
+}
+
+impl From<Repository> for HostedRepository {
+    fn from(repo: Repository) -> Self {
+        HostedRepository {
+            full_name: repo.full_name,
+            description: repo.description.filter(|description| !description.is_empty()),
+            default_branch: repo.default_branch,
+            private: repo.private,
+            web_url: repo.html_url,
+            clone_url: repo.clone_url,
+            size: repo.size,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Pull {
+    number: u64,
+    title: String,
+    state: String,
+    #[serde(default)]
+    merged: bool,
+    user: Option<User>,
+    head: Branch,
+    base: Branch,
+    // Only reported by Gitea 1.22 and later
+    #[serde(default)]
+    draft: bool,
+    html_url: String,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<Pull> for PullRequest {
+    fn from(pull: Pull) -> Self {
+        let state = match (pull.state.as_str(), pull.merged) {
+            ("open", _) => PullRequestState::Open,
+            (_, true) => PullRequestState::Merged,
+            _ => PullRequestState::Closed,
+        };
+        PullRequest {
+            number: pull.number,
+            title: pull.title,
+            state,
+            author: pull.user.map(|user| user.login).unwrap_or_default(),
+            source_branch: pull.head.name,
+            target_branch: pull.base.name,
+            draft: pull.draft,
+            web_url: pull.html_url,
+            created_at: pull.created_at,
+            updated_at: pull.updated_at,
+        }
+    }
+}
+
+#[async_trait]
+impl HostingProvider for GiteaProvider {
+    // Gitea has no device authorization flow
+    fn oauth_endpoints(&self) -> OAuthEndpoints {
+        let web_url = &self.host.web_url;
+        OAuthEndpoints {
+            authorize_url: format!("{}/login/oauth/authorize", web_url),
+            token_url: format!("{}/login/oauth/access_token", web_url),
+            device_code_url: None,
+        }
+    }
+
+    // Gitea OAuth tokens carry the app's full access
+    fn oauth_scopes(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    async fn current_user(&self, token: &str) -> Result<HostedUser, HostingError> {
+        let user = get(&self.client, &self.host, token, "/user").await?.json::<User>().await?;
+        Ok(HostedUser {
+            id: user.id,
+            login: user.login,
+            name: Some(user.full_name).filter(|name| !name.is_empty()),
+            avatar_url: user.avatar_url,
+            scopes: Vec::new(),
+        })
+    }
+
+    async fn list_repositories(&self, token: &str) -> Result<Vec<HostedRepository>, HostingError> {
+        let repos = get_pages::<Repository>(&self.client, &self.host, token, "/user/repos", "limit", PER_PAGE).await?;
+        Ok(repos.into_iter().map(HostedRepository::from).collect())
+    }
+
+    async fn repository(&self, token: &str, full_name: &str) -> Result<HostedRepository, HostingError> {
+        let path = format!("/repos/{}", full_name);
+        let repo = get(&self.client, &self.host, token, &path).await?.json::<Repository>().await?;
+        Ok(repo.into())
+    }
+
+    // Gitea has no contributors endpoint
+    async fn contributor_count(&self, _token: &str, _full_name: &str) -> Result<Option<usize>, HostingError> {
+        Ok(None)
+    }
+
+    async fn list_pull_requests(
+        &self,
+        token: &str,
+        full_name: &str,
+        state: PullRequestState,
+    ) -> Result<Vec<PullRequest>, HostingError> {
+        // Like GitHub, merged pulls are closed ones with `merged` set
+        let query = match state {
+            PullRequestState::Open => "open",
+            PullRequestState::Closed | PullRequestState::Merged => "closed",
+            PullRequestState::All => "all",
+        };
+        let path = format!("/repos/{}/pulls?state={}", full_name, query);
+        let pulls = get_pages::<Pull>(&self.client, &self.host, token, &path, "limit", PER_PAGE).await?;
+        Ok(pulls.into_iter()
+            .map(PullRequest::from)
+            .filter(|pull| state == PullRequestState::All || pull.state == state)
+            .collect())
+    }
+
+    async fn add_ssh_key(&self, token: &str, title: &str, public_key: &str) -> Result<(), HostingError> {
+        post(&self.client, &self.host, token, "/user/keys", &NewSshKey { title, key: public_key }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosting::ProviderKind;
+    use crate::test_support::{StubResponse, StubServer};
+    use serde_json::{json, Value};
+
+    fn provider(server: &StubServer) -> GiteaProvider {
+        let host = Host::new(ProviderKind::Gitea, Some(&server.url), Some(&server.url));
+        GiteaProvider::new(host, reqwest::Client::new())
+    }
+
+    fn repository(n: usize) -> Value {
+        json!({
+            "full_name": format!("dev/repo-{}", n),
+            "description": "",
+            "default_branch": "main",
+            "private": false,
+            "html_url": format!("https://git.example.com/dev/repo-{}", n),
+            "clone_url": format!("https://git.example.com/dev/repo-{}.git", n),
+        })
+    }
+
+    fn pull(number: u64, state: &str, merged: bool) -> Value {
+        json!({
+            "number": number,
+            "title": format!("Pull {}", number),
+            "state": state,
+            "merged": merged,
+            "user": { "id": 1, "login": "dev" },
+            "head": { "ref": "feature" },
+            "base": { "ref": "main" },
+            "html_url": format!("https://git.example.com/dev/hello/pulls/{}", number),
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-02T00:00:00Z",
+        })
+    }
+
+    #[tokio::test]
+    async fn reads_the_user_without_an_empty_name() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!({ "id": 3, "login": "dev", "full_name": "", "avatar_url": null }))
+        }).await;
+
+        let user = provider(&server).current_user("token").await.unwrap();
+
+        assert_eq!(server.requests()[0].path(), "/user");
+        assert_eq!((user.id, user.login.as_str(), user.name), (3, "dev", None));
+    }
+
+    #[tokio::test]
+    async fn lists_repositories_with_limit_pages() {
+        let server = StubServer::start(|request| {
+            let repos: Vec<Value> = match request.query("page").as_deref() {
+                Some("1") => (0..PER_PAGE).map(repository).collect(),
+                _ => Vec::new(),
+            };
+            StubResponse::json(200, json!(repos))
+        }).await;
+
+        let repos = provider(&server).list_repositories("token").await.unwrap();
+
+        assert_eq!(repos.len(), PER_PAGE);
+        assert_eq!(repos[0].description, None);
+        let pages: Vec<String> = server.requests().iter().map(|request| request.target.clone()).collect();
+        assert_eq!(pages, vec!["/user/repos?limit=50&page=1", "/user/repos?limit=50&page=2"]);
+    }
+
+    #[tokio::test]
+    async fn closed_pulls_with_merged_set_are_merged() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!([pull(1, "open", false), pull(2, "closed", true), pull(3, "closed", false)]))
+        }).await;
+        let provider = provider(&server);
+
+        let all = provider.list_pull_requests("token", "dev/hello", PullRequestState::All).await.unwrap();
+        let states: Vec<_> = all.iter().map(|pull| pull.state).collect();
+        assert_eq!(states, vec![PullRequestState::Open, PullRequestState::Merged, PullRequestState::Closed]);
+
+        let closed = provider.list_pull_requests("token", "dev/hello", PullRequestState::Closed).await.unwrap();
+        assert_eq!(closed.iter().map(|pull| pull.number).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(server.requests()[1].query("state").as_deref(), Some("closed"));
+    }
+
+    #[test]
+    fn has_no_device_flow() {
+        let host = Host::new(ProviderKind::Gitea, Some("git.example.com"), None);
+        let endpoints = GiteaProvider::new(host, reqwest::Client::new()).oauth_endpoints();
+        assert_eq!(endpoints.authorize_url, "https://git.example.com/login/oauth/authorize");
+        assert!(endpoints.device_code_url.is_none());
+    }
+}
@@ -0,0 +1,299 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{get, get_pages, post, Host, HostedRepository, HostedUser, HostingError, HostingProvider, NewSshKey, OAuthEndpoints, PullRequest, PullRequestState};
+
+const PER_PAGE: usize = 100;
+
+// github.com and GitHub Enterprise Server (REST API v3)
+pub struct GitHubProvider {
+    host: Host,
+    client: reqwest::Client,
+}
+
+impl GitHubProvider {
+    pub fn new(host: Host, client: reqwest::Client) -> Self {
+        GitHubProvider { host, client }
+    }
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: u64,
+    login: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+    description: Option<String>,
+    default_branch: Option<String>,
+    private: bool,
+    html_url: String,
+    clone_url: String,
+    #[serde(default)]
+    size: u64,
+}
+
+impl From<Repository> for HostedRepository {
+    fn from(repo: Repository) -> Self {
+        HostedRepository {
+            full_name: repo.full_name,
+            description: repo.description,
+            default_branch: repo.default_branch,
+            private: repo.private,
+            web_url: repo.html_url,
+            clone_url: repo.clone_url,
+            size: repo.size,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Pull {
+    number: u64,
+    title: String,
+    state: String,
+    merged_at: Option<String>,
+    user: Option<User>,
+    head: Branch,
+    base: Branch,
+    #[serde(default)]
+    draft: bool,
+    html_url: String,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<Pull> for PullRequest {
+    fn from(pull: Pull) -> Self {
+        let state = match (pull.state.as_str(), &pull.merged_at) {
+            ("open", _) => PullRequestState::Open,
+            (_, Some(_)) => PullRequestState::Merged,
+            _ => PullRequestState::Closed,
+        };
+        PullRequest {
+            number: pull.number,
+            title: pull.title,
+            state,
+            author: pull.user.map(|user| user.login).unwrap_or_default(),
+            source_branch: pull.head.name,
+            target_branch: pull.base.name,
+            draft: pull.draft,
+            web_url: pull.html_url,
+            created_at: pull.created_at,
+            updated_at: pull.updated_at,
+        }
+    }
+}
+
+#[async_trait]
+impl HostingProvider for GitHubProvider {
+    fn oauth_endpoints(&self) -> OAuthEndpoints {
+        let web_url = &self.host.web_url;
+        OAuthEndpoints {
+            authorize_url: format!("{}/login/oauth/authorize", web_url),
+            token_url: format!("{}/login/oauth/access_token", web_url),
+            device_code_url: Some(format!("{}/login/device/code", web_url)),
+        }
+    }
+
+    fn oauth_scopes(&self) -> &'static [&'static str] {
+        &["repo", "user", "write:public_key"]
+    }
+
+    // Lets users signed in to several GitHub accounts pick one
+    fn authorize_params(&self) -> &'static [(&'static str, &'static str)] {
+        &[("prompt", "select_account")]
+    }
+
+    fn ssh_key_scope(&self) -> Option<&'static str> {
+        Some("write:public_key")
+    }
+
+    async fn current_user(&self, token: &str) -> Result<HostedUser, HostingError> {
+        let response = get(&self.client, &self.host, token, "/user").await?;
+        // Classic OAuth and personal access tokens list their scopes here;
+        // fine-grained tokens send nothing
+        let scopes = response.headers()
+            .get("x-oauth-scopes")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value.split(',')
+                    .map(str::trim)
+                    .filter(|scope| !scope.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let user = response.json::<User>().await?;
+        Ok(HostedUser {
+            id: user.id,
+            login: user.login,
+            name: user.name,
+            avatar_url: user.avatar_url,
+            scopes,
+        })
+    }
+
+    async fn list_repositories(&self, token: &str) -> Result<Vec<HostedRepository>, HostingError> {
+        let repos = get_pages::<Repository>(&self.client, &self.host, token, "/user/repos", "per_page", PER_PAGE).await?;
+        Ok(repos.into_iter().map(HostedRepository::from).collect())
+    }
+
+    async fn repository(&self, token: &str, full_name: &str) -> Result<HostedRepository, HostingError> {
+        let path = format!("/repos/{}", full_name);
+        let repo = get(&self.client, &self.host, token, &path).await?.json::<Repository>().await?;
+        Ok(repo.into())
+    }
+
+    // With one contributor per page, the page number of the "last" link is the count
+    async fn contributor_count(&self, token: &str, full_name: &str) -> Result<Option<usize>, HostingError> {
+        let path = format!("/repos/{}/contributors?per_page=1&anon=1", full_name);
+        let response = get(&self.client, &self.host, token, &path).await?;
+        // Empty repositories answer 204 without a body
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(Some(0));
+        }
+        let last_page = response.headers()
+            .get("link")
+            .and_then(|link| link.to_str().ok())
+            .and_then(|link| link.split(',').find(|l| l.contains("rel=\"last\"")))
+            .and_then(|last| last.split("&page=").nth(1))
+            .and_then(|page| page.split(['>', '&']).next())
+            .and_then(|page| page.parse().ok());
+        match last_page {
+            Some(count) => Ok(Some(count)),
+            // No pagination: zero or one contributor
+            None => Ok(Some(response.json::<Vec<serde_json::Value>>().await?.len())),
+        }
+    }
+
+    async fn list_pull_requests(
+        &self,
+        token: &str,
+        full_name: &str,
+        state: PullRequestState,
+    ) -> Result<Vec<PullRequest>, HostingError> {
+        // GitHub has no merged filter; merged pulls are closed ones with merged_at
+        let query = match state {
+            PullRequestState::Open => "open",
+            PullRequestState::Closed | PullRequestState::Merged => "closed",
+            PullRequestState::All => "all",
+        };
+        let path = format!("/repos/{}/pulls?state={}", full_name, query);
+        let pulls = get_pages::<Pull>(&self.client, &self.host, token, &path, "per_page", PER_PAGE).await?;
+        Ok(pulls.into_iter()
+            .map(PullRequest::from)
+            .filter(|pull| state == PullRequestState::All || pull.state == state)
+            .collect())
+    }
+
+    async fn add_ssh_key(&self, token: &str, title: &str, public_key: &str) -> Result<(), HostingError> {
+        post(&self.client, &self.host, token, "/user/keys", &NewSshKey { title, key: public_key }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosting::ProviderKind;
+    use crate::test_support::{StubResponse, StubServer};
+    use serde_json::{json, Value};
+
+    fn provider(server: &StubServer) -> GitHubProvider {
+        let host = Host::new(ProviderKind::GitHub, Some(&server.url), Some(&server.url));
+        GitHubProvider::new(host, reqwest::Client::new())
+    }
+
+    fn repository(n: usize) -> Value {
+        json!({
+            "full_name": format!("octocat/repo-{}", n),
+            "description": null,
+            "default_branch": "main",
+            "private": n == 0,
+            "html_url": format!("https://github.com/octocat/repo-{}", n),
+            "clone_url": format!("https://github.com/octocat/repo-{}.git", n),
+            "size": 12,
+        })
+    }
+
+    fn pull(number: u64, state: &str, merged_at: Option<&str>) -> Value {
+        json!({
+            "number": number,
+            "title": format!("Pull {}", number),
+            "state": state,
+            "merged_at": merged_at,
+            "user": { "id": 1, "login": "octocat" },
+            "head": { "ref": "feature" },
+            "base": { "ref": "main" },
+            "html_url": format!("https://github.com/octocat/hello/pull/{}", number),
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-02T00:00:00Z",
+        })
+    }
+
+    #[tokio::test]
+    async fn reads_the_user_and_token_scopes() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!({ "id": 583231, "login": "octocat", "name": "The Octocat", "avatar_url": null }))
+                .with_header("X-OAuth-Scopes", "repo, user, write:public_key")
+        }).await;
+
+        let user = provider(&server).current_user("token").await.unwrap();
+
+        assert_eq!(server.requests()[0].path(), "/user");
+        assert_eq!((user.id, user.login.as_str(), user.name.as_deref()), (583231, "octocat", Some("The Octocat")));
+        assert_eq!(user.scopes, vec!["repo", "user", "write:public_key"]);
+    }
+
+    #[tokio::test]
+    async fn lists_repositories_across_pages() {
+        let server = StubServer::start(|request| {
+            let repos: Vec<Value> = match request.query("page").as_deref() {
+                Some("1") => (0..PER_PAGE).map(repository).collect(),
+                _ => vec![repository(PER_PAGE)],
+            };
+            StubResponse::json(200, json!(repos))
+        }).await;
+
+        let repos = provider(&server).list_repositories("token").await.unwrap();
+
+        assert_eq!(repos.len(), PER_PAGE + 1);
+        assert_eq!(repos[0].full_name, "octocat/repo-0");
+        assert_eq!(repos[0].web_url, "https://github.com/octocat/repo-0");
+        assert!(repos[0].private);
+        let pages: Vec<String> = server.requests().iter().map(|request| request.target.clone()).collect();
+        assert_eq!(pages, vec!["/user/repos?per_page=100&page=1", "/user/repos?per_page=100&page=2"]);
+    }
+
+    #[tokio::test]
+    async fn closed_pulls_with_merged_at_are_merged() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!([
+                pull(1, "open", None),
+                pull(2, "closed", Some("2024-01-03T00:00:00Z")),
+                pull(3, "closed", None),
+            ]))
+        }).await;
+        let provider = provider(&server);
+
+        let all = provider.list_pull_requests("token", "octocat/hello", PullRequestState::All).await.unwrap();
+        let states: Vec<_> = all.iter().map(|pull| pull.state).collect();
+        assert_eq!(states, vec![PullRequestState::Open, PullRequestState::Merged, PullRequestState::Closed]);
+        assert_eq!((all[0].source_branch.as_str(), all[0].target_branch.as_str()), ("feature", "main"));
+
+        let merged = provider.list_pull_requests("token", "octocat/hello", PullRequestState::Merged).await.unwrap();
+        assert_eq!(merged.iter().map(|pull| pull.number).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(server.requests()[1].query("state").as_deref(), Some("closed"));
+    }
+}
@@ -0,0 +1,290 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{get, get_pages, post, Host, HostedRepository, HostedUser, HostingError, HostingProvider, NewSshKey, OAuthEndpoints, PullRequest, PullRequestState};
+
+const PER_PAGE: usize = 100;
+
+// gitlab.com and self-managed GitLab (REST API v4). Merge requests are
+// reported as pull requests.
+pub struct GitLabProvider {
+    host: Host,
+    client: reqwest::Client,
+}
+
+impl GitLabProvider {
+    pub fn new(host: Host, client: reqwest::Client) -> Self {
+        GitLabProvider { host, client }
+    }
+}
+
+// Projects are addressed by their URL-encoded path, group/subgroup/name
+fn project_path(full_name: &str) -> String {
+    let id: String = oauth2::url::form_urlencoded::byte_serialize(full_name.as_bytes()).collect();
+    format!("/projects/{}", id)
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: u64,
+    username: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Statistics {
+    // In bytes
+    #[serde(default)]
+    repository_size: u64,
+}
+
+#[derive(Deserialize)]
+struct Project {
+    path_with_namespace: String,
+    description: Option<String>,
+    default_branch: Option<String>,
+    // "public", "internal" or "private"
+    visibility: String,
+    web_url: String,
+    http_url_to_repo: String,
+    // Only sent with statistics=true to members who may see them
+    #[serde(default)]
+    statistics: Statistics,
+}
+
+impl From<Project> for HostedRepository {
+    fn from(project: Project) -> Self {
+        HostedRepository {
+            full_name: project.path_with_namespace,
+            description: project.description,
+            default_branch: project.default_branch,
+            private: project.visibility != "public",
+            web_url: project.web_url,
+            clone_url: project.http_url_to_repo,
+            size: project.statistics.repository_size / 1024,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Author {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    iid: u64,
+    title: String,
+    // "opened", "closed", "locked" or "merged"
+    state: String,
+    author: Option<Author>,
+    source_branch: String,
+    target_branch: String,
+    #[serde(default)]
+    draft: bool,
+    // Pre-15.0 name of `draft`
+    #[serde(default)]
+    work_in_progress: bool,
+    web_url: String,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<MergeRequest> for PullRequest {
+    fn from(mr: MergeRequest) -> Self {
+        let state = match mr.state.as_str() {
+            "opened" | "locked" => PullRequestState::Open,
+            "merged" => PullRequestState::Merged,
+            _ => PullRequestState::Closed,
+        };
+        PullRequest {
+            number: mr.iid,
+            title: mr.title,
+            state,
+            author: mr.author.map(|author| author.username).unwrap_or_default(),
+            source_branch: mr.source_branch,
+            target_branch: mr.target_branch,
+            draft: mr.draft || mr.work_in_progress,
+            web_url: mr.web_url,
+            created_at: mr.created_at,
+            updated_at: mr.updated_at,
+        }
+    }
+}
+
+#[async_trait]
+impl HostingProvider for GitLabProvider {
+    fn oauth_endpoints(&self) -> OAuthEndpoints {
+        let web_url = &self.host.web_url;
+        OAuthEndpoints {
+            authorize_url: format!("{}/oauth/authorize", web_url),
+            token_url: format!("{}/oauth/token", web_url),
+            device_code_url: Some(format!("{}/oauth/authorize_device", web_url)),
+        }
+    }
+
+    fn oauth_scopes(&self) -> &'static [&'static str] {
+        &["api", "read_user", "write_repository"]
+    }
+
+    async fn current_user(&self, token: &str) -> Result<HostedUser, HostingError> {
+        let user = get(&self.client, &self.host, token, "/user").await?.json::<User>().await?;
+        Ok(HostedUser {
+            id: user.id,
+            login: user.username,
+            name: user.name,
+            avatar_url: user.avatar_url,
+            // GitLab doesn't report a token's scopes alongside the user
+            scopes: Vec::new(),
+        })
+    }
+
+    async fn list_repositories(&self, token: &str) -> Result<Vec<HostedRepository>, HostingError> {
+        let projects = get_pages::<Project>(
+            &self.client,
+            &self.host,
+            token,
+            "/projects?membership=true&order_by=last_activity_at",
+            "per_page",
+            PER_PAGE,
+        ).await?;
+        Ok(projects.into_iter().map(HostedRepository::from).collect())
+    }
+
+    async fn repository(&self, token: &str, full_name: &str) -> Result<HostedRepository, HostingError> {
+        let path = format!("{}?statistics=true", project_path(full_name));
+        let project = get(&self.client, &self.host, token, &path).await?.json::<Project>().await?;
+        Ok(project.into())
+    }
+
+    // Read from the X-Total header of a one-item page, which GitLab leaves
+    // out for very large result sets
+    async fn contributor_count(&self, token: &str, full_name: &str) -> Result<Option<usize>, HostingError> {
+        let path = format!("{}/repository/contributors?per_page=1", project_path(full_name));
+        let response = get(&self.client, &self.host, token, &path).await?;
+        Ok(response.headers()
+            .get("x-total")
+            .and_then(|total| total.to_str().ok())
+            .and_then(|total| total.parse().ok()))
+    }
+
+    async fn list_pull_requests(
+        &self,
+        token: &str,
+        full_name: &str,
+        state: PullRequestState,
+    ) -> Result<Vec<PullRequest>, HostingError> {
+        let query = match state {
+            PullRequestState::Open => "opened",
+            PullRequestState::Closed => "closed",
+            PullRequestState::Merged => "merged",
+            PullRequestState::All => "all",
+        };
+        let path = format!("{}/merge_requests?state={}", project_path(full_name), query);
+        let merge_requests = get_pages::<MergeRequest>(&self.client, &self.host, token, &path, "per_page", PER_PAGE).await?;
+        Ok(merge_requests.into_iter().map(PullRequest::from).collect())
+    }
+
+    async fn add_ssh_key(&self, token: &str, title: &str, public_key: &str) -> Result<(), HostingError> {
+        post(&self.client, &self.host, token, "/user/keys", &NewSshKey { title, key: public_key }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosting::ProviderKind;
+    use crate::test_support::{StubResponse, StubServer};
+    use serde_json::{json, Value};
+
+    fn provider(server: &StubServer) -> GitLabProvider {
+        let host = Host::new(ProviderKind::GitLab, Some(&server.url), Some(&server.url));
+        GitLabProvider::new(host, reqwest::Client::new())
+    }
+
+    fn merge_request(iid: u64, state: &str) -> Value {
+        json!({
+            "iid": iid,
+            "title": format!("MR {}", iid),
+            "state": state,
+            "author": { "username": "dev" },
+            "source_branch": "feature",
+            "target_branch": "main",
+            "work_in_progress": iid == 1,
+            "web_url": format!("https://gitlab.com/group/sub/project/-/merge_requests/{}", iid),
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-02T00:00:00Z",
+        })
+    }
+
+    #[test]
+    fn encodes_subgroup_paths() {
+        assert_eq!(project_path("group/sub/my.project"), "/projects/group%2Fsub%2Fmy.project");
+    }
+
+    #[tokio::test]
+    async fn reads_the_user() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!({ "id": 7, "username": "dev", "name": "Dev", "avatar_url": "https://example.com/a.png" }))
+        }).await;
+
+        let user = provider(&server).current_user("token").await.unwrap();
+
+        assert_eq!(server.requests()[0].path(), "/user");
+        assert_eq!((user.id, user.login.as_str()), (7, "dev"));
+        assert_eq!(user.avatar_url.as_deref(), Some("https://example.com/a.png"));
+        assert!(user.scopes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn maps_merge_request_states() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!([
+                merge_request(1, "opened"),
+                merge_request(2, "locked"),
+                merge_request(3, "merged"),
+                merge_request(4, "closed"),
+            ]))
+        }).await;
+
+        let pulls = provider(&server).list_pull_requests("token", "group/sub/project", PullRequestState::All)
+            .await
+            .unwrap();
+
+        let states: Vec<_> = pulls.iter().map(|pull| pull.state).collect();
+        assert_eq!(states, vec![
+            PullRequestState::Open,
+            PullRequestState::Open,
+            PullRequestState::Merged,
+            PullRequestState::Closed,
+        ]);
+        assert!(pulls[0].draft && !pulls[1].draft);
+        assert_eq!(pulls[0].author, "dev");
+        assert_eq!(
+            server.requests()[0].target,
+            "/projects/group%2Fsub%2Fproject/merge_requests?state=all&per_page=100&page=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn repository_sizes_are_converted_to_kilobytes() {
+        let server = StubServer::start(|_| {
+            StubResponse::json(200, json!({
+                "path_with_namespace": "group/sub/project",
+                "description": "A project",
+                "default_branch": "main",
+                "visibility": "internal",
+                "web_url": "https://gitlab.com/group/sub/project",
+                "http_url_to_repo": "https://gitlab.com/group/sub/project.git",
+                "statistics": { "repository_size": 2048 },
+            }))
+        }).await;
+
+        let repo = provider(&server).repository("token", "group/sub/project").await.unwrap();
+
+        assert_eq!(server.requests()[0].target, "/projects/group%2Fsub%2Fproject?statistics=true");
+        assert_eq!((repo.full_name.as_str(), repo.size, repo.private), ("group/sub/project", 2, true));
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Serialize, Deserialize};
+use similar::{Algorithm, DiffTag};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::git_commands::DiffLine;
+
+// Give up on a single line pair quickly rather than stall a large diff
+const LINE_DIFF_TIMEOUT: Duration = Duration::from_millis(20);
+
+// Very long lines (minified files etc.) are left without inline spans
+const MAX_LINE_BYTES: usize = 10_000;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InlineGranularity {
+    None,
+    #[default]
+    Word,
+    Char,
+}
+
+// A changed range in a line's content, in UTF-16 code units so it indexes
+// the line as a JavaScript string does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Pairs each run of removed lines with the added lines that directly follow
+// it and marks the parts of each pair that actually changed
+pub fn annotate_lines(lines: &mut [DiffLine], granularity: InlineGranularity) {
+    if granularity == InlineGranularity::None {
+        return;
+    }
+
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].origin != '-' {
+            i += 1;
+            continue;
+        }
+
+        let removed_start = i;
+        while i < lines.len() && lines[i].origin == '-' {
+            i += 1;
+        }
+        let added_start = i;
+        while i < lines.len() && lines[i].origin == '+' {
+            i += 1;
+        }
+
+        let pairs = (added_start - removed_start).min(i - added_start);
+        for offset in 0..pairs {
+            let old = &lines[removed_start + offset].content;
+            let new = &lines[added_start + offset].content;
+            if old.len() > MAX_LINE_BYTES || new.len() > MAX_LINE_BYTES {
+                continue;
+            }
+
+            let (old_spans, new_spans) = diff_line_pair(old, new, granularity);
+            lines[removed_start + offset].spans = old_spans;
+            lines[added_start + offset].spans = new_spans;
+        }
+    }
+}
+
+fn diff_line_pair(old: &str, new: &str, granularity: InlineGranularity) -> (Vec<ChangeSpan>, Vec<ChangeSpan>) {
+    let old_tokens = tokenize(old, granularity);
+    let new_tokens = tokenize(new, granularity);
+    let ops = similar::capture_diff_slices_deadline(
+        Algorithm::Myers,
+        &old_tokens,
+        &new_tokens,
+        Some(Instant::now() + LINE_DIFF_TIMEOUT),
+    );
+
+    (
+        collect_spans(&old_tokens, ops.iter().filter(|op| op.tag() != DiffTag::Equal).map(|op| op.old_range())),
+        collect_spans(&new_tokens, ops.iter().filter(|op| op.tag() != DiffTag::Equal).map(|op| op.new_range())),
+    )
+}
+
+// Words are runs of alphanumerics, whitespace runs stay together and every
+// other character (punctuation, operators) is a token of its own
+fn tokenize(line: &str, granularity: InlineGranularity) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((idx, c)) = chars.next() {
+        let end = idx + c.len_utf8();
+        let continues = |next: char| match granularity {
+            InlineGranularity::Char => false,
+            _ if is_word_char(c) => is_word_char(next),
+            _ if c.is_whitespace() => next.is_whitespace(),
+            _ => false,
+        };
+
+        match chars.peek() {
+            Some(&(_, next)) if continues(next) => {}
+            _ => {
+                tokens.push(&line[start..end]);
+                start = end;
+            }
+        }
+    }
+
+    tokens
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Converts token ranges into merged UTF-16 ranges
+fn collect_spans(tokens: &[&str], ranges: impl Iterator<Item = Range<usize>>) -> Vec<ChangeSpan> {
+    let mut offsets = Vec::with_capacity(tokens.len() + 1);
+    offsets.push(0);
+    for token in tokens {
+        offsets.push(offsets[offsets.len() - 1] + token.encode_utf16().count());
+    }
+
+    let mut spans = Vec::new();
+    for range in ranges.filter(|range| !range.is_empty()) {
+        push_span(&mut spans, offsets[range.start], offsets[range.end] - offsets[range.start]);
+    }
+    spans
+}
+
+fn push_span(spans: &mut Vec<ChangeSpan>, start: usize, len: usize) {
+    match spans.last_mut() {
+        Some(last) if last.end == start => last.end += len,
+        _ => spans.push(ChangeSpan { start, end: start + len }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(origin: char, content: &str) -> DiffLine {
+        DiffLine {
+            origin,
+            old_lineno: None,
+            new_lineno: None,
+            content: content.to_string(),
+            spans: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    fn spans(line: &DiffLine) -> Vec<(usize, usize)> {
+        line.spans.iter().map(|span| (span.start, span.end)).collect()
+    }
+
+    #[test]
+    fn marks_changed_words_of_paired_lines() {
+        let mut lines = vec![
+            line(' ', "fn main() {"),
+            line('-', "    let total = count + 1;"),
+            line('+', "    let total = amount + 1;"),
+            line('+', "    println!();"),
+        ];
+
+        annotate_lines(&mut lines, InlineGranularity::Word);
+
+        assert_eq!(spans(&lines[1]), vec![(16, 21)]);
+        assert_eq!(spans(&lines[2]), vec![(16, 22)]);
+        assert!(lines[0].spans.is_empty() && lines[3].spans.is_empty());
+    }
+
+    #[test]
+    fn counts_offsets_in_utf16_code_units() {
+        // 😀 is two UTF-16 code units, é one
+        let mut lines = vec![line('-', "let 😀 = \"é\" + 1;"), line('+', "let 😀 = \"é\" + 2;")];
+        annotate_lines(&mut lines, InlineGranularity::Word);
+        assert_eq!(spans(&lines[0]), vec![(15, 16)]);
+        assert_eq!(spans(&lines[1]), vec![(15, 16)]);
+
+        let mut lines = vec![line('-', "a😀b"), line('+', "a😃b")];
+        annotate_lines(&mut lines, InlineGranularity::Char);
+        assert_eq!(spans(&lines[0]), vec![(1, 3)]);
+        assert_eq!(spans(&lines[1]), vec![(1, 3)]);
+    }
+}
@@ -2,9 +2,24 @@
 
 mod git_commands;
 mod github_auth;
+mod inline_diff;
+mod syntax_highlight;
+mod search;
+mod watcher;
+mod status_cache;
+mod repo_list;
+mod settings;
+mod credentials;
+mod accounts;
+mod hosting;
+mod remote_auth;
+mod ssh_keys;
+mod host_keys;
+#[cfg(test)]
+mod test_support;
 
 use git_commands::GitRepo;
-use github_auth::GitHubAuth;
+use github_auth::HostAuth;
 use std::sync::Arc;
 use tauri::State;
 use tauri::Manager;
@@ -13,17 +28,70 @@ use std::sync::atomic::Ordering;
 use parking_lot::Mutex as PLMutex;
 use tauri::{CustomMenuItem, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem};
 use std::sync::Mutex;
-use crate::git_commands::DiffEntry;
+use crate::git_commands::{DiffEntry, DiffViewOptions, BinaryDiffAssets, PatchTarget, PatchCheckReport};
+use crate::git_commands::{BlameHunk, BlameFileOptions, FileHistoryEntry, TreeEntryInfo, FileContent};
 use dirs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-
-// Make RepoState thread-safe using parking_lot::Mutex
-pub struct RepoState(Arc<PLMutex<Option<GitRepo>>>);
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use crate::search::{CodeSearchOptions, CodeMatch, HistorySearchOptions, HistorySearchEvent};
+use crate::status_cache::RepoStatus;
+use crate::repo_list::{KnownRepository, RepoList};
+use crate::settings::{Settings, SettingsStore};
+use crate::credentials::CredentialStore;
+use crate::accounts::{Account, AccountBinding, AccountRegistry};
+use crate::hosting::{HostedRepository, ProviderKind, PullRequest, PullRequestState};
+use crate::remote_auth::{CredentialOptions, PassphrasePrompt, StoredToken};
+use crate::ssh_keys::SshKeyInfo;
+use crate::host_keys::{HostKeyPrompt, HostVerification, UnknownHostKey};
+use std::time::Duration;
+
+// Open repositories keyed by their canonical path. Each repository has its
+// own lock, so a long operation in one tab doesn't block the others.
+#[derive(Default)]
+pub struct RepoState(Arc<PLMutex<HashMap<PathBuf, Arc<PLMutex<GitRepo>>>>>);
 
 impl RepoState {
     fn new() -> Self {
-        RepoState(Arc::new(PLMutex::new(None)))
+        RepoState::default()
+    }
+
+    fn key(path: &str) -> PathBuf {
+        std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+    }
+
+    fn insert(&self, path: &str, repo: GitRepo) {
+        self.0.lock().insert(Self::key(path), Arc::new(PLMutex::new(repo)));
+    }
+
+    fn remove(&self, path: &str) -> bool {
+        self.0.lock().remove(&Self::key(path)).is_some()
+    }
+
+    fn get(&self, path: &str) -> Option<Arc<PLMutex<GitRepo>>> {
+        self.0.lock().get(&Self::key(path)).cloned()
+    }
+
+    fn with_repo<T>(&self, path: &str, f: impl FnOnce(&mut GitRepo) -> Result<T, String>) -> Result<T, String> {
+        let repo = self.get(path)
+            .ok_or_else(|| format!("No repository opened at {}", path))?;
+        let mut repo = repo.lock();
+        f(&mut repo)
+    }
+
+    // with_repo on the blocking thread pool, for network operations that can
+    // wait minutes on the remote or on a passphrase or host key prompt
+    async fn with_repo_blocking<T: Send + 'static>(
+        &self,
+        path: &str,
+        f: impl FnOnce(&mut GitRepo) -> Result<T, String> + Send + 'static,
+    ) -> Result<T, String> {
+        let repo = self.get(path)
+            .ok_or_else(|| format!("No repository opened at {}", path))?;
+        tokio::task::spawn_blocking(move || f(&mut repo.lock()))
+            .await
+            .map_err(|e| e.to_string())?
     }
 }
 
@@ -33,11 +101,12 @@ unsafe impl Sync for RepoState {}
 
 // Define AuthState in main.rs
 #[derive(Default)]
-pub struct AuthState(Arc<PLMutex<Option<GitHubAuth>>>);
+pub struct AuthState(Arc<PLMutex<Option<HostAuth>>>);
 
 impl AuthState {
-    fn new() -> Self {
-        AuthState(Arc::new(PLMutex::new(Some(GitHubAuth::new()))))
+    // Left uninitialised when the HTTP client can't be set up
+    fn new(settings: &Settings, accounts: Arc<PLMutex<AccountRegistry>>) -> Self {
+        AuthState(Arc::new(PLMutex::new(HostAuth::new(settings, accounts).ok())))
     }
 }
 
@@ -45,16 +114,164 @@ impl AuthState {
 unsafe impl Send for AuthState {}
 unsafe impl Sync for AuthState {}
 
+// Signed-in GitHub accounts and the repositories bound to them
+pub struct AccountsState(Arc<PLMutex<AccountRegistry>>);
+
+impl AccountsState {
+    // Token of the account bound to `remote` of the repository, if any
+    fn token_for(&self, repo_path: &str, remote: &str) -> Result<Option<StoredToken>, String> {
+        let bound = self.0.lock().token_for(repo_path, Some(remote)).map_err(|e| e.to_string())?;
+        Ok(bound.and_then(|(account, token)| stored_token(&account, token)))
+    }
+
+    // The given account, or the default one, with its token; None if no
+    // account is given and there is no default
+    fn account(&self, account_id: Option<&str>) -> Result<Option<(Account, String)>, String> {
+        let accounts = self.0.lock();
+        let account = match account_id {
+            Some(id) => Some(accounts.get(id).ok_or_else(|| format!("Unknown account: {}", id))?),
+            None => accounts.default_account(),
+        };
+        let account = match account {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+        let token = accounts.token(&account.id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("No token stored for {}", account.login))?;
+        Ok(Some((account, token)))
+    }
+}
+
+fn stored_token(account: &Account, token: String) -> Option<StoredToken> {
+    let host = remote_auth::url_host(&account.host.web_url)?;
+    Some(StoredToken { host, token })
+}
+
+// How long a network operation waits for the user to answer a prompt
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Clone, serde::Serialize)]
+struct PassphraseRequest {
+    request_id: String,
+    key_path: String,
+}
+
+// Passphrase prompts waiting for an answer from the UI, keyed by request id
+#[derive(Default)]
+pub struct PassphraseState(Arc<PLMutex<HashMap<String, std::sync::mpsc::Sender<Option<String>>>>>);
+
+impl PassphraseState {
+    // Emits "ssh-passphrase-required" and blocks the git operation until
+    // answer_ssh_passphrase replies or the prompt times out
+    fn prompt(&self, app_handle: &tauri::AppHandle) -> PassphrasePrompt {
+        let pending = self.0.clone();
+        let app_handle = app_handle.clone();
+        Arc::new(move |key: &Path| {
+            let request_id = uuid::Uuid::new_v4().to_string();
+            let (sender, receiver) = std::sync::mpsc::channel();
+            pending.lock().insert(request_id.clone(), sender);
+            let _ = app_handle.emit_all("ssh-passphrase-required", PassphraseRequest {
+                request_id: request_id.clone(),
+                key_path: key.to_string_lossy().into_owned(),
+            });
+            let answer = receiver.recv_timeout(PROMPT_TIMEOUT).ok().flatten();
+            pending.lock().remove(&request_id);
+            answer
+        })
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+struct HostKeyRequest {
+    request_id: String,
+    #[serde(flatten)]
+    key: UnknownHostKey,
+}
+
+// First-use host key prompts waiting for an answer from the UI, keyed by
+// request id
+#[derive(Default)]
+pub struct HostKeyState(Arc<PLMutex<HashMap<String, std::sync::mpsc::Sender<bool>>>>);
+
+impl HostKeyState {
+    // Emits "ssh-host-key-unknown" and blocks the git operation until
+    // answer_ssh_host_key replies; no answer rejects the key
+    fn prompt(&self, app_handle: &tauri::AppHandle) -> HostKeyPrompt {
+        let pending = self.0.clone();
+        let app_handle = app_handle.clone();
+        Arc::new(move |key: &UnknownHostKey| {
+            let request_id = uuid::Uuid::new_v4().to_string();
+            let (sender, receiver) = std::sync::mpsc::channel();
+            pending.lock().insert(request_id.clone(), sender);
+            let _ = app_handle.emit_all("ssh-host-key-unknown", HostKeyRequest {
+                request_id: request_id.clone(),
+                key: key.clone(),
+            });
+            let accepted = receiver.recv_timeout(PROMPT_TIMEOUT).unwrap_or(false);
+            pending.lock().remove(&request_id);
+            accepted
+        })
+    }
+}
+
+// Credentials for a network operation: the given token plus the configured
+// SSH keys, with passphrases and unknown host keys asked about through the UI
+fn credential_options(
+    app_handle: &tauri::AppHandle,
+    token: Option<StoredToken>,
+    settings: &SettingsState,
+    passphrases: &PassphraseState,
+    host_keys: &HostKeyState,
+) -> CredentialOptions {
+    let settings = settings.0.lock();
+    let ssh = &settings.get().ssh;
+    let tls = &settings.get().tls;
+    CredentialOptions {
+        token,
+        key_files: ssh.key_files.iter().map(PathBuf::from).collect(),
+        host_keys: ssh.host_keys.iter()
+            .map(|(host, key)| (host.to_lowercase(), PathBuf::from(key)))
+            .collect(),
+        passphrase_prompt: Some(passphrases.prompt(app_handle)),
+        verification: HostVerification {
+            trusted_certificates: tls.trusted_certificates.iter()
+                .map(|(host, fingerprint)| (host.to_lowercase(), fingerprint.clone()))
+                .collect(),
+            host_key_prompt: Some(host_keys.prompt(app_handle)),
+            ..HostVerification::default()
+        },
+    }
+}
+
+// settings.json, shared by every command that needs configuration
+pub struct SettingsState(Arc<PLMutex<SettingsStore>>);
+
+// Persisted list of known local repositories, loaded in setup once the
+// config dir is known
+pub struct RepoListState(Arc<PLMutex<RepoList>>);
+
+// Cancellation flags for running history searches, keyed by search id
+#[derive(Default)]
+pub struct SearchState(Arc<PLMutex<HashMap<String, Arc<AtomicBool>>>>);
+
 // Add this near your other state management
 #[derive(Default)]
 struct WindowState {
 }
 
 #[tauri::command]
-async fn open_repository(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+async fn open_repository(
+    app_handle: tauri::AppHandle,
+    path: String,
+    state: State<'_, RepoState>,
+    repo_list: State<'_, RepoListState>,
+) -> Result<String, String> {
     match GitRepo::open(&path, None) {
-        Ok(repo) => {
-            *state.0.lock() = Some(repo);
+        Ok(mut repo) => {
+            repo.watch(app_handle).map_err(|e| e.to_string())?;
+            state.insert(&path, repo);
+            repo_list.0.lock().record_opened(&path).map_err(|e| e.to_string())?;
             Ok("Repository opened successfully".into())
         }
         Err(e) => Err(e.to_string()),
@@ -62,48 +279,133 @@ async fn open_repository(path: String, state: State<'_, RepoState>) -> Result<St
 }
 
 #[tauri::command]
-async fn push_changes(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
-        repo.push(false).map_err(|e| e.to_string())?;
-        Ok("Changes pushed successfully".into())
+async fn close_repository(path: String, state: State<'_, RepoState>) -> Result<(), String> {
+    if state.remove(&path) {
+        Ok(())
     } else {
-        Err("No repository opened".into())
+        Err(format!("No repository opened at {}", path))
     }
 }
 
 #[tauri::command]
-async fn push_changes_remote(_repo_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
-        repo.push(true).map_err(|e| e.to_string())?;
+async fn list_open_repositories(state: State<'_, RepoState>) -> Result<Vec<String>, String> {
+    let mut paths: Vec<String> = state.0.lock()
+        .keys()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+#[tauri::command]
+async fn list_known_repositories(repo_list: State<'_, RepoListState>) -> Result<Vec<KnownRepository>, String> {
+    Ok(repo_list.0.lock().list())
+}
+
+#[tauri::command]
+async fn list_repository_groups(repo_list: State<'_, RepoListState>) -> Result<Vec<String>, String> {
+    Ok(repo_list.0.lock().groups())
+}
+
+#[tauri::command]
+async fn set_repository_favourite(path: String, favourite: bool, repo_list: State<'_, RepoListState>) -> Result<(), String> {
+    repo_list.0.lock().set_favourite(&path, favourite).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_repository_groups(path: String, groups: Vec<String>, repo_list: State<'_, RepoListState>) -> Result<(), String> {
+    repo_list.0.lock().set_groups(&path, groups).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn forget_repository(path: String, repo_list: State<'_, RepoListState>) -> Result<(), String> {
+    repo_list.0.lock().remove(&path).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn prune_missing_repositories(repo_list: State<'_, RepoListState>) -> Result<Vec<String>, String> {
+    repo_list.0.lock().prune_missing().map_err(|e| e.to_string())
+}
+
+// Returns repositories below `root` that aren't in the list yet, for the
+// user to pick from before calling `import_repositories`
+#[tauri::command]
+async fn scan_for_repositories(
+    root: String,
+    max_depth: Option<usize>,
+    repo_list: State<'_, RepoListState>,
+) -> Result<Vec<String>, String> {
+    let max_depth = max_depth.unwrap_or(repo_list::DEFAULT_SCAN_DEPTH);
+    let found = tokio::task::spawn_blocking(move || repo_list::scan(Path::new(&root), max_depth))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let list = repo_list.0.lock();
+    Ok(found.into_iter().filter(|path| !list.contains(path)).collect())
+}
+
+#[tauri::command]
+async fn import_repositories(paths: Vec<String>, repo_list: State<'_, RepoListState>) -> Result<usize, String> {
+    repo_list.0.lock().import(&paths).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn push_changes(
+    app_handle: tauri::AppHandle,
+    path: String,
+    state: State<'_, RepoState>,
+    accounts: State<'_, AccountsState>,
+    settings: State<'_, SettingsState>,
+    passphrases: State<'_, PassphraseState>,
+    host_keys: State<'_, HostKeyState>,
+) -> Result<String, String> {
+    let token = accounts.token_for(&path, "origin")?;
+    let credentials = credential_options(&app_handle, token, &settings, &passphrases, &host_keys);
+    state.with_repo_blocking(&path, move |repo| {
+        repo.push(false, credentials).map_err(|e| e.to_string())?;
         Ok("Changes pushed successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    }).await
 }
 
 #[tauri::command]
-async fn revert_commit(_path: String, commit_hash: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn push_changes_remote(
+    app_handle: tauri::AppHandle,
+    repo_path: String,
+    state: State<'_, RepoState>,
+    accounts: State<'_, AccountsState>,
+    settings: State<'_, SettingsState>,
+    passphrases: State<'_, PassphraseState>,
+    host_keys: State<'_, HostKeyState>,
+) -> Result<String, String> {
+    let token = accounts.token_for(&repo_path, "origin")?;
+    let credentials = credential_options(&app_handle, token, &settings, &passphrases, &host_keys);
+    state.with_repo_blocking(&repo_path, move |repo| {
+        repo.push(true, credentials).map_err(|e| e.to_string())?;
+        Ok("Changes pushed successfully".into())
+    }).await
+}
+
+#[tauri::command]
+async fn revert_commit(path: String, commit_hash: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.revert_commit(&commit_hash, false).map_err(|e| e.to_string())?;
         Ok("Commit reverted successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn revert_commit_remote(_repo_path: String, commit_hash: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn revert_commit_remote(repo_path: String, commit_hash: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&repo_path, |repo| {
         repo.revert_commit(&commit_hash, true).map_err(|e| e.to_string())?;
         Ok("Commit reverted successfully. A new branch has been created.".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
 async fn github_auth(
     window: tauri::Window,
+    provider: Option<ProviderKind>,
+    host: Option<String>,
     state: State<'_, AuthState>,
 ) -> Result<String, String> {
     let auth = {
@@ -112,25 +414,36 @@ async fn github_auth(
             .ok_or_else(|| "Authentication not initialized".to_string())?
             .clone()
     }; // MutexGuard is dropped here
-    auth.start_login(window).await.map_err(|e| e.to_string())
+    let provider = provider.unwrap_or_default();
+    // Without a client secret the browser redirect flow can't exchange the code
+    if auth.has_client_secret(provider, host.as_deref()) {
+        auth.start_login(window, provider, host.as_deref()).await.map_err(|e| e.to_string())
+    } else {
+        auth.start_device_login(window, provider, host.as_deref()).await.map_err(|e| e.to_string())
+    }
 }
 
 #[tauri::command]
-async fn handle_auth_callback(
-    code: String,
+async fn github_device_auth(
+    window: tauri::Window,
+    provider: Option<ProviderKind>,
+    host: Option<String>,
     state: State<'_, AuthState>,
-) -> Result<(), String> {
+) -> Result<String, String> {
     let auth = {
         let lock = state.0.lock();
         lock.as_ref()
             .ok_or_else(|| "Authentication not initialized".to_string())?
             .clone()
     }; // MutexGuard is dropped here
-    auth.handle_callback(code).await.map_err(|e| e.to_string())
+    auth.start_device_login(window, provider.unwrap_or_default(), host.as_deref())
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 async fn list_github_repos(
+    account_id: Option<String>,
     state: State<'_, AuthState>,
 ) -> Result<Vec<String>, String> {
     let auth = {
@@ -139,7 +452,57 @@ async fn list_github_repos(
             .ok_or_else(|| "Authentication not initialized".to_string())?
             .clone()
     }; // MutexGuard is dropped here
-    auth.list_repositories().await.map_err(|e| e.to_string())
+    auth.list_repositories(account_id.as_deref()).await.map_err(|e| e.to_string())
+}
+
+// Repositories of the account with their descriptions, visibility and URLs
+#[tauri::command]
+async fn list_hosted_repositories(
+    account_id: Option<String>,
+    state: State<'_, AuthState>,
+) -> Result<Vec<HostedRepository>, String> {
+    let auth = {
+        let lock = state.0.lock();
+        lock.as_ref()
+            .ok_or_else(|| "Authentication not initialized".to_string())?
+            .clone()
+    };
+    auth.list_hosted_repositories(account_id.as_deref()).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_hosted_repository(
+    full_name: String,
+    account_id: Option<String>,
+    state: State<'_, AuthState>,
+) -> Result<HostedRepository, String> {
+    let auth = {
+        let lock = state.0.lock();
+        lock.as_ref()
+            .ok_or_else(|| "Authentication not initialized".to_string())?
+            .clone()
+    };
+    auth.hosted_repository(&full_name, account_id.as_deref()).await.map_err(|e| e.to_string())
+}
+
+// Pull requests, or merge requests on GitLab, of a hosted repository; open
+// ones unless `state` says otherwise
+#[tauri::command]
+async fn list_pull_requests(
+    full_name: String,
+    state: Option<PullRequestState>,
+    account_id: Option<String>,
+    auth_state: State<'_, AuthState>,
+) -> Result<Vec<PullRequest>, String> {
+    let auth = {
+        let lock = auth_state.0.lock();
+        lock.as_ref()
+            .ok_or_else(|| "Authentication not initialized".to_string())?
+            .clone()
+    };
+    auth.list_pull_requests(&full_name, state.unwrap_or_default(), account_id.as_deref())
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -148,10 +511,18 @@ async fn get_repository_stats(path: String) -> Result<RepositoryStats, String> {
     repo.get_stats().map_err(|e| e.to_string())
 }
 
+// Signs one account out, the default account if none is given
 #[tauri::command]
-async fn github_logout(state: State<'_, AuthState>) -> Result<(), String> {
-    let mut auth = state.0.lock();
-    *auth = Some(GitHubAuth::new());
+async fn github_logout(
+    app_handle: tauri::AppHandle,
+    account_id: Option<String>,
+    state: State<'_, AuthState>,
+) -> Result<(), String> {
+    let auth = state.0.lock();
+    if let Some(auth) = auth.as_ref() {
+        auth.remove_account(account_id.as_deref()).map_err(|e| e.to_string())?;
+    }
+    let _ = app_handle.emit_all("accounts-changed", ());
     Ok(())
 }
 
@@ -177,6 +548,7 @@ async fn check_auth_status(state: State<'_, AuthState>) -> Result<bool, String>
 #[tauri::command]
 async fn get_remote_repository_stats(
     repo_name: String,
+    account_id: Option<String>,
     state: State<'_, AuthState>,
 ) -> Result<RepositoryStats, String> {
     let auth = {
@@ -186,44 +558,59 @@ async fn get_remote_repository_stats(
             .clone()
     };
     
-    auth.get_repository_stats(&repo_name)
+    auth.get_repository_stats(&repo_name, account_id.as_deref())
         .await
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn pull_changes(_path: String, is_remote: bool, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
-        repo.pull(is_remote).map_err(|e| e.to_string())?;
+async fn pull_changes(
+    app_handle: tauri::AppHandle,
+    path: String,
+    is_remote: bool,
+    state: State<'_, RepoState>,
+    accounts: State<'_, AccountsState>,
+    settings: State<'_, SettingsState>,
+    passphrases: State<'_, PassphraseState>,
+    host_keys: State<'_, HostKeyState>,
+) -> Result<String, String> {
+    let token = accounts.token_for(&path, "origin")?;
+    let credentials = credential_options(&app_handle, token, &settings, &passphrases, &host_keys);
+    state.with_repo_blocking(&path, move |repo| {
+        repo.pull(is_remote, credentials).map_err(|e| e.to_string())?;
         Ok("Changes pulled successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    }).await
 }
 
+// Returns the canonical path of the new clone
 #[tauri::command]
 async fn clone_repository(
     app_handle: tauri::AppHandle,
     repo_url: String, 
-    state: State<'_, RepoState>
+    account_id: Option<String>,
+    state: State<'_, RepoState>,
+    repo_list: State<'_, RepoListState>,
+    accounts: State<'_, AccountsState>,
+    auth_state: State<'_, AuthState>,
+    settings: State<'_, SettingsState>,
+    passphrases: State<'_, PassphraseState>,
+    host_keys: State<'_, HostKeyState>,
 ) -> Result<String, String> {
-    let token = std::env::var("GITHUB_TOKEN")
-        .map_err(|_| "GitHub token not found".to_string())?;
+    // Clone with the chosen account, or the default one, and keep the
+    // repository bound to it afterwards. SSH and public HTTPS clones also
+    // work without any account.
+    let account = accounts.account(account_id.as_deref())?;
+    let token = account.as_ref().and_then(|(account, token)| stored_token(account, token.clone()));
 
+    // The last path segment of https://host/owner/repo.git or git@host:repo.git
     let repo_name = repo_url
-        .split('/')
-        .last()
+        .rsplit(['/', ':'])
+        .next()
+        .filter(|name| !name.is_empty())
         .ok_or_else(|| "Invalid repository URL".to_string())?
         .trim_end_matches(".git");
 
-    // Use the saved clone directory if available
-    let clone_path = if let Ok(saved_dir) = get_saved_clone_directory(app_handle).await {
-        PathBuf::from(saved_dir).join(repo_name)
-    } else if let Ok(custom_dir) = std::env::var("CLONE_DIRECTORY") {
-        PathBuf::from(custom_dir).join(repo_name)
-    } else {
-        get_exe_dir()?.join("repositories").join(repo_name)
-    };
+    let clone_path = settings.0.lock().get().clone_directory().join(repo_name);
 
     // Check if directory exists and is not empty
     if clone_path.exists() {
@@ -238,24 +625,45 @@ async fn clone_repository(
             .map_err(|e| format!("Failed to create parent directory: {}", e))?;
     }
 
-    // Validate token before attempting clone
-    let client = reqwest::Client::new();
-    let response = client
-        .get("https://api.github.com/user")
-        .header("Authorization", format!("Bearer {}", token))
-        .header("User-Agent", "simplegit")
-        .send()
-        .await
-        .map_err(|e| format!("Failed to validate token: {}", e))?;
-
-    if !response.status().is_success() {
-        return Err("Invalid or expired GitHub token. Please re-authenticate.".to_string());
+    // Validate the token before an HTTPS clone that would send it
+    let sends_token = token.as_ref().is_some_and(|stored| {
+        repo_url.starts_with("https://")
+            && remote_auth::url_host(&repo_url).as_deref() == Some(stored.host.as_str())
+    });
+    if let (true, Some((account, token))) = (sends_token, &account) {
+        let auth = auth_state.0.lock().clone()
+            .ok_or_else(|| "Authentication not initialized".to_string())?;
+        let is_valid = auth.validate_token(token, &account.host)
+            .await
+            .map_err(|e| format!("Failed to validate token: {}", e))?;
+        if !is_valid {
+            return Err(format!("Invalid or expired token for {}. Please re-authenticate.", account.host.web_url));
+        }
     }
 
-    match GitRepo::clone(&repo_url, &clone_path) {
-        Ok(repo) => {
-            *state.0.lock() = Some(repo);
-            Ok(format!("Repository cloned successfully to {}", clone_path.display()))
+    let credentials = credential_options(&app_handle, token, &settings, &passphrases, &host_keys);
+    let cloned = {
+        let (repo_url, clone_path) = (repo_url.clone(), clone_path.clone());
+        tokio::task::spawn_blocking(move || GitRepo::clone(&repo_url, &clone_path, credentials))
+            .await
+            .map_err(|e| e.to_string())?
+    };
+    match cloned {
+        Ok(mut repo) => {
+            repo.watch(app_handle).map_err(|e| e.to_string())?;
+            // The frontend passes this path to every later command
+            let clone_path = std::fs::canonicalize(&clone_path).unwrap_or(clone_path);
+            let clone_path_str = clone_path.to_string_lossy().into_owned();
+            state.insert(&clone_path_str, repo);
+            repo_list.0.lock().record_opened(&clone_path_str).map_err(|e| e.to_string())?;
+            {
+                let mut accounts = accounts.0.lock();
+                accounts.forget_repository(&clone_path_str).map_err(|e| e.to_string())?;
+                if let Some((account, _)) = account {
+                    accounts.bind(&clone_path_str, None, Some(account.id)).map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(clone_path_str)
         }
         Err(e) => {
             // Clean up failed clone attempt
@@ -267,16 +675,250 @@ async fn clone_repository(
     }
 }
 
+// Adds an account from a personal access token, on github.com unless
+// `provider` and `host` name another instance
 #[tauri::command]
-async fn set_github_token(token: String, state: State<'_, AuthState>) -> Result<(), String> {
-    std::env::set_var("GITHUB_TOKEN", &token);
-    let auth = state.0.lock();
-    if let Some(auth) = auth.as_ref() {
-        auth.set_access_token(token);
+async fn set_github_token(
+    token: String,
+    provider: Option<ProviderKind>,
+    host: Option<String>,
+    state: State<'_, AuthState>,
+) -> Result<Account, String> {
+    let auth = {
+        let lock = state.0.lock();
+        lock.as_ref()
+            .ok_or_else(|| "Authentication not initialized".to_string())?
+            .clone()
+    };
+    let host = auth.host(provider.unwrap_or_default(), host.as_deref());
+    auth.add_account(token, &host).await.map_err(|e| e.to_string())
+}
+
+// Validates the tokens kept from previous sessions, signing out accounts
+// their host no longer accepts. Returns whether any account is left.
+#[tauri::command]
+async fn check_stored_token(
+    state: State<'_, AuthState>,
+    accounts: State<'_, AccountsState>,
+) -> Result<bool, String> {
+    let auth = {
+        let lock = state.0.lock();
+        lock.as_ref()
+            .ok_or_else(|| "Authentication not initialized".to_string())?
+            .clone()
+    };
+    auth.migrate_legacy_token().await.map_err(|e| e.to_string())?;
+
+    let stored = accounts.0.lock().list();
+    for account in stored {
+        let token = match auth.account(Some(&account.id)) {
+            Ok((_, token)) => token,
+            Err(_) => continue,
+        };
+        match auth.validate_token(&token, &account.host).await {
+            Ok(true) => {}
+            Ok(false) => auth.remove_account(Some(&account.id)).map_err(|e| e.to_string())?,
+            // Keep the account if its host can't be reached right now
+            Err(e) => eprintln!("Could not validate the token of {} on {}: {}", account.login, account.host.web_url, e),
+        }
     }
+    Ok(!accounts.0.lock().is_empty())
+}
+
+// Web address of the account's host, for building clone and browser URLs
+#[tauri::command]
+async fn get_github_web_url(
+    account_id: Option<String>,
+    accounts: State<'_, AccountsState>,
+) -> Result<String, String> {
+    let accounts = accounts.0.lock();
+    let account = match account_id {
+        Some(id) => accounts.get(&id),
+        None => accounts.default_account(),
+    };
+    Ok(account.map(|account| account.host).unwrap_or_default().web_url)
+}
+
+#[tauri::command]
+async fn list_github_accounts(accounts: State<'_, AccountsState>) -> Result<Vec<Account>, String> {
+    Ok(accounts.0.lock().list())
+}
+
+#[tauri::command]
+async fn set_default_github_account(
+    app_handle: tauri::AppHandle,
+    account_id: String,
+    accounts: State<'_, AccountsState>,
+) -> Result<(), String> {
+    accounts.0.lock().set_default(&account_id).map_err(|e| e.to_string())?;
+    let _ = app_handle.emit_all("accounts-changed", ());
     Ok(())
 }
 
+// Binds a repository, or just one of its remotes, to an account;
+// no account removes the binding
+#[tauri::command]
+async fn bind_repository_account(
+    path: String,
+    remote: Option<String>,
+    account_id: Option<String>,
+    accounts: State<'_, AccountsState>,
+) -> Result<(), String> {
+    accounts.0.lock().bind(&path, remote, account_id).map_err(|e| e.to_string())
+}
+
+// The account that clone/push/pull and API calls would use for the remote
+#[tauri::command]
+async fn get_repository_account(
+    path: String,
+    remote: Option<String>,
+    accounts: State<'_, AccountsState>,
+) -> Result<Option<Account>, String> {
+    Ok(accounts.0.lock().account_for(&path, remote.as_deref()))
+}
+
+#[tauri::command]
+async fn list_repository_account_bindings(
+    path: String,
+    accounts: State<'_, AccountsState>,
+) -> Result<Vec<AccountBinding>, String> {
+    Ok(accounts.0.lock().bindings(&path))
+}
+
+// Key pairs in ~/.ssh
+#[tauri::command]
+async fn list_ssh_keys() -> Result<Vec<SshKeyInfo>, String> {
+    ssh_keys::list_keys(&ssh_keys::ssh_dir()).map_err(|e| e.to_string())
+}
+
+// Creates ~/.ssh/<name> (id_ed25519 by default) and its .pub
+#[tauri::command]
+async fn generate_ssh_key(
+    name: Option<String>,
+    comment: Option<String>,
+    passphrase: Option<String>,
+) -> Result<SshKeyInfo, String> {
+    ssh_keys::generate_ed25519(
+        &ssh_keys::ssh_dir(),
+        name.as_deref(),
+        comment.as_deref().unwrap_or_default(),
+        passphrase.as_deref(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_ssh_public_key(path: String) -> Result<String, String> {
+    ssh_keys::public_key(Path::new(&path)).map_err(|e| e.to_string())
+}
+
+// Adds the public key of `path` to the account on its host
+#[tauri::command]
+async fn upload_ssh_key(
+    path: String,
+    title: Option<String>,
+    account_id: Option<String>,
+    state: State<'_, AuthState>,
+) -> Result<String, String> {
+    let auth = {
+        let lock = state.0.lock();
+        lock.as_ref()
+            .ok_or_else(|| "Authentication not initialized".to_string())?
+            .clone()
+    };
+    let public_key = ssh_keys::public_key(Path::new(&path)).map_err(|e| e.to_string())?;
+    let title = title.filter(|title| !title.trim().is_empty()).unwrap_or_else(|| {
+        let name = Path::new(&path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        format!("SimpleGit ({})", name)
+    });
+    auth.add_ssh_key(&title, &public_key, account_id.as_deref())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(format!("Added the key \"{}\"", title))
+}
+
+// Makes remotes on `host` use only the given key; no key goes back to the
+// agent and the default keys
+#[tauri::command]
+async fn set_host_ssh_key(
+    app_handle: tauri::AppHandle,
+    host: String,
+    key_path: Option<String>,
+    settings: State<'_, SettingsState>,
+    auth_state: State<'_, AuthState>,
+    accounts: State<'_, AccountsState>,
+) -> Result<Settings, String> {
+    let host = host.trim().to_lowercase();
+    if host.is_empty() {
+        return Err("Host name is required".to_string());
+    }
+    let mut new_settings = settings.0.lock().get().clone();
+    match key_path.filter(|path| !path.is_empty()) {
+        Some(key_path) => {
+            if !Path::new(&key_path).is_file() {
+                return Err(format!("{} does not exist", key_path));
+            }
+            new_settings.ssh.host_keys.insert(host, key_path);
+        }
+        None => {
+            new_settings.ssh.host_keys.remove(&host);
+        }
+    }
+    apply_settings(&app_handle, &settings, &auth_state, &accounts, new_settings)
+}
+
+// Answers an "ssh-passphrase-required" prompt; no passphrase skips the key
+#[tauri::command]
+async fn answer_ssh_passphrase(
+    request_id: String,
+    passphrase: Option<String>,
+    passphrases: State<'_, PassphraseState>,
+) -> Result<(), String> {
+    let sender = passphrases.0.lock().remove(&request_id)
+        .ok_or_else(|| "The passphrase prompt has expired".to_string())?;
+    sender.send(passphrase).map_err(|_| "The passphrase prompt has expired".to_string())
+}
+
+// Answers an "ssh-host-key-unknown" prompt; accepted keys are added to
+// ~/.ssh/known_hosts
+#[tauri::command]
+async fn answer_ssh_host_key(
+    request_id: String,
+    accept: bool,
+    host_keys: State<'_, HostKeyState>,
+) -> Result<(), String> {
+    let sender = host_keys.0.lock().remove(&request_id)
+        .ok_or_else(|| "The host key prompt has expired".to_string())?;
+    sender.send(accept).map_err(|_| "The host key prompt has expired".to_string())
+}
+
+// Accepts the TLS certificate with this SHA-256 fingerprint from `host`
+// even if it doesn't validate; no fingerprint stops trusting it
+#[tauri::command]
+async fn trust_tls_certificate(
+    app_handle: tauri::AppHandle,
+    host: String,
+    fingerprint: Option<String>,
+    settings: State<'_, SettingsState>,
+    auth_state: State<'_, AuthState>,
+    accounts: State<'_, AccountsState>,
+) -> Result<Settings, String> {
+    let host = host.trim().to_lowercase();
+    if host.is_empty() {
+        return Err("Host name is required".to_string());
+    }
+    let mut new_settings = settings.0.lock().get().clone();
+    match fingerprint.filter(|fingerprint| !fingerprint.trim().is_empty()) {
+        Some(fingerprint) => {
+            new_settings.tls.trusted_certificates.insert(host, fingerprint.trim().to_string());
+        }
+        None => {
+            new_settings.tls.trusted_certificates.remove(&host);
+        }
+    }
+    apply_settings(&app_handle, &settings, &auth_state, &accounts, new_settings)
+}
+
 #[tauri::command]
 async fn cleanup_before_close(state: State<'_, AuthState>) -> Result<(), String> {
     let auth = state.0.lock();
@@ -287,89 +929,294 @@ async fn cleanup_before_close(state: State<'_, AuthState>) -> Result<(), String>
     Ok(())
 }
 
+// Whether a token is accepted, on github.com unless `provider` and `host`
+// name another instance
 #[tauri::command]
-async fn validate_github_token(token: String, state: State<'_, AuthState>) -> Result<bool, String> {
+async fn validate_github_token(
+    token: String,
+    provider: Option<ProviderKind>,
+    host: Option<String>,
+    state: State<'_, AuthState>,
+) -> Result<bool, String> {
     let auth = {
         let lock = state.0.lock();
         lock.as_ref()
             .ok_or_else(|| "Authentication not initialized".to_string())?
             .clone()
     };
-    
-    auth.validate_token(&token)
+    let host = auth.host(provider.unwrap_or_default(), host.as_deref());
+    auth.validate_token(&token, &host)
         .await
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn stash_changes(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn stash_changes(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.stash_changes().map_err(|e| e.to_string())?;
         Ok("Changes stashed successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn stash_pop(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn stash_pop(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.stash_pop().map_err(|e| e.to_string())?;
         Ok("Stashed changes applied successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn create_tag(_path: String, tag_name: String, message: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn create_tag(path: String, tag_name: String, message: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.create_tag(&tag_name, &message).map_err(|e| e.to_string())?;
         Ok(format!("Tag '{}' created successfully", tag_name))
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn reset_hard(_path: String, commit_hash: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn reset_hard(path: String, commit_hash: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.reset_hard(&commit_hash).map_err(|e| e.to_string())?;
         Ok("Repository reset successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn list_remotes(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn list_remotes(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         let remotes = repo.list_remotes().map_err(|e| e.to_string())?;
         Ok(remotes.join(", "))
-    } else {
-        Err("No repository opened".into())
+    })
+}
+
+#[tauri::command]
+async fn get_status(path: String, state: State<'_, RepoState>) -> Result<RepoStatus, String> {
+    state.with_repo(&path, |repo| {
+        repo.status().map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn view_diff(path: String, options: Option<DiffViewOptions>, state: State<'_, RepoState>) -> Result<Vec<DiffEntry>, String> {
+    let options = options.unwrap_or_default();
+
+    // Diffing a repository that isn't open in a tab still works, it just
+    // goes through a temporary handle
+    if state.get(&path).is_none() {
+        let repo = GitRepo::open(&path, None).map_err(|e| e.to_string())?;
+        return repo.view_diff(&options).map_err(|e| e.to_string());
     }
+
+    state.with_repo(&path, |repo| {
+        repo.view_diff(&options).map_err(|e| e.to_string())
+    })
 }
 
 #[tauri::command]
-async fn view_diff(path: String) -> Result<Vec<DiffEntry>, String> {
-    println!("Debug - Received path in backend: {}", path);
-    
-    let repo = GitRepo::open(&path, None)
-        .map_err(|e| e.to_string())?;
-    
-    repo.view_diff()
+async fn diff_revisions(
+    path: String,
+    from: String,
+    to: Option<String>,
+    options: Option<DiffViewOptions>,
+    state: State<'_, RepoState>,
+) -> Result<Vec<DiffEntry>, String> {
+    state.with_repo(&path, |repo| {
+        repo.diff_revisions(&from, to.as_deref(), &options.unwrap_or_default())
+            .map_err(|e| e.to_string())
+    })
+}
+
+// Where binary diff versions are written for the webview to load. Must stay
+// inside the asset protocol scope ($APPDATA/**).
+fn diff_assets_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let data_dir = app_handle.path_resolver()
+        .app_data_dir()
+        .ok_or_else(|| "Could not find app data directory".to_string())?;
+    Ok(data_dir.join("diff-assets"))
+}
+
+#[tauri::command]
+async fn get_binary_diff_assets(
+    app_handle: tauri::AppHandle,
+    path: String,
+    file_path: String,
+    old_id: Option<String>,
+    new_id: Option<String>,
+    state: State<'_, RepoState>,
+) -> Result<BinaryDiffAssets, String> {
+    let assets_dir = diff_assets_dir(&app_handle)?;
+    state.with_repo(&path, |repo| {
+        repo.export_binary_versions(old_id.as_deref(), new_id.as_deref(), &file_path, &assets_dir)
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn export_patches(path: String, range: String, dest_dir: String, state: State<'_, RepoState>) -> Result<Vec<String>, String> {
+    state.with_repo(&path, |repo| {
+        repo.export_patches(&range, Path::new(&dest_dir))
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn export_working_diff(path: String, destination: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
+        repo.export_working_diff(Path::new(&destination))
+            .map_err(|e| e.to_string())?;
+        Ok(format!("Patch saved to {}", destination))
+    })
+}
+
+#[tauri::command]
+async fn apply_patch(path: String, patch_file: String, target: PatchTarget, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
+        repo.apply_patch(Path::new(&patch_file), target)
+            .map_err(|e| e.to_string())?;
+        Ok("Patch applied successfully".into())
+    })
+}
+
+#[tauri::command]
+async fn check_patch(path: String, patch_file: String, target: PatchTarget, state: State<'_, RepoState>) -> Result<PatchCheckReport, String> {
+    state.with_repo(&path, |repo| {
+        repo.check_patch(Path::new(&patch_file), target)
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn search_code(
+    path: String,
+    rev: String,
+    options: CodeSearchOptions,
+    state: State<'_, RepoState>,
+) -> Result<Vec<CodeMatch>, String> {
+    state.with_repo(&path, |repo| {
+        repo.search_code(&rev, &options)
+            .map_err(|e| e.to_string())
+    })
+}
+
+// Results are streamed as `search-history-result` events while the search
+// runs; the command itself resolves with the number of matches
+#[tauri::command]
+async fn search_history(
+    window: tauri::Window,
+    path: String,
+    search_id: String,
+    options: HistorySearchOptions,
+    state: State<'_, RepoState>,
+    search_state: State<'_, SearchState>,
+) -> Result<usize, String> {
+    let repo_path = state.with_repo(&path, |repo| Ok(repo.get_path()))?;
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    search_state.0.lock().insert(search_id.clone(), cancelled.clone());
+
+    // Search on its own repository handle so other commands aren't blocked
+    let event_id = search_id.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let repo = git2::Repository::open(&repo_path).map_err(|e| e.to_string())?;
+        search::search_history(&repo, &options, &cancelled, |result| {
+            let event = HistorySearchEvent { search_id: event_id.clone(), result };
+            let _ = window.emit("search-history-result", event);
+        })
         .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string());
+
+    search_state.0.lock().remove(&search_id);
+    result?
 }
 
 #[tauri::command]
-async fn get_repository_settings(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn cancel_search(search_id: String, search_state: State<'_, SearchState>) -> Result<(), String> {
+    if let Some(cancelled) = search_state.0.lock().get(&search_id) {
+        cancelled.store(true, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_repository_settings(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         let settings = repo.get_repository_settings().map_err(|e| e.to_string())?;
         Ok(format!("Repository settings retrieved successfully: {}", settings))
-    } else {
-        Err("No repository opened".into())
-    }
+    })
+}
+
+#[tauri::command]
+async fn blame_file(
+    path: String,
+    file_path: String,
+    rev: Option<String>,
+    options: Option<BlameFileOptions>,
+    state: State<'_, RepoState>,
+) -> Result<Vec<BlameHunk>, String> {
+    state.with_repo(&path, |repo| {
+        repo.blame_file(&file_path, rev.as_deref(), &options.unwrap_or_default())
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn file_history(
+    path: String,
+    file_path: String,
+    rev: Option<String>,
+    max_count: Option<usize>,
+    state: State<'_, RepoState>,
+) -> Result<Vec<FileHistoryEntry>, String> {
+    state.with_repo(&path, |repo| {
+        repo.file_history(&file_path, rev.as_deref(), max_count)
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn list_tree(
+    path: String,
+    rev: String,
+    dir: Option<String>,
+    state: State<'_, RepoState>,
+) -> Result<Vec<TreeEntryInfo>, String> {
+    state.with_repo(&path, |repo| {
+        repo.list_tree(&rev, dir.as_deref())
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn read_file_at_revision(
+    path: String,
+    rev: String,
+    file_path: String,
+    max_bytes: Option<usize>,
+    highlight: Option<bool>,
+    state: State<'_, RepoState>,
+) -> Result<FileContent, String> {
+    state.with_repo(&path, |repo| {
+        repo.read_file_at(&rev, &file_path, max_bytes, highlight.unwrap_or(false))
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[tauri::command]
+async fn save_file_at_revision(
+    path: String,
+    rev: String,
+    file_path: String,
+    destination: String,
+    state: State<'_, RepoState>,
+) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
+        repo.save_file_at(&rev, &file_path, Path::new(&destination))
+            .map_err(|e| e.to_string())?;
+        Ok(format!("Saved '{}' at {} to {}", file_path, rev, destination))
+    })
 }
 
 #[tauri::command]
@@ -392,224 +1239,172 @@ fn create_tray_menu() -> SystemTrayMenu {
         .add_item(quit)
 }
 
-#[tauri::command]
-async fn set_clone_directory(app_handle: tauri::AppHandle, path: String) -> Result<(), String> {
-    std::fs::create_dir_all(&path)
-        .map_err(|e| format!("Failed to create directory: {}", e))?;
-    
-    // Get config dir from app_handle
-    let config_dir = app_handle.path_resolver()
-        .app_config_dir()
-        .ok_or_else(|| "Could not find config directory".to_string())?;
-    
-    // Ensure config directory exists
-    std::fs::create_dir_all(&config_dir)
-        .map_err(|e| format!("Failed to create config directory: {}", e))?;
-    
-    // Save to config file
-    let config_file = config_dir.join("clone_directory.txt");
-    std::fs::write(&config_file, &path)
-        .map_err(|e| format!("Failed to save config: {}", e))?;
-    
-    // Also set environment variable for current session
-    std::env::set_var("CLONE_DIRECTORY", path);
-    Ok(())
-}
+// Applies new settings and tells every window about them. Changing the
+// OAuth app recreates the GitHub client, which signs the user out.
+fn apply_settings(
+    app_handle: &tauri::AppHandle,
+    settings_state: &SettingsState,
+    auth_state: &AuthState,
+    accounts: &AccountsState,
+    new_settings: Settings,
+) -> Result<Settings, String> {
+    let mut store = settings_state.0.lock();
+    let github_changed = store.get().github != new_settings.github
+        || store.get().github_enterprise != new_settings.github_enterprise
+        || store.get().gitlab != new_settings.gitlab
+        || store.get().gitea != new_settings.gitea;
+    if !store.set(new_settings).map_err(|e| e.to_string())? {
+        return Ok(store.get().clone());
+    }
 
-#[tauri::command]
-async fn get_saved_clone_directory(app_handle: tauri::AppHandle) -> Result<String, String> {
-    let config_dir = app_handle.path_resolver()
-        .app_config_dir()
-        .ok_or_else(|| "Could not find config directory".to_string())?;
-    
-    let config_file = config_dir.join("clone_directory.txt");
-    
-    if config_file.exists() {
-        std::fs::read_to_string(config_file)
-            .map_err(|e| format!("Failed to read config: {}", e))
-    } else {
-        // Return default if no saved directory
-        Ok(dirs::home_dir()
-            .ok_or_else(|| "Could not find home directory".to_string())?
-            .join(".simplegit")
-            .to_string_lossy()
-            .to_string())
+    if github_changed {
+        *auth_state.0.lock() = HostAuth::new(store.get(), accounts.0.clone()).ok();
     }
+    let settings = store.get().clone();
+    let _ = app_handle.emit_all("settings-changed", settings.clone());
+    Ok(settings)
 }
 
-fn get_exe_dir() -> Result<PathBuf, String> {
-    std::env::current_exe()
-        .map_err(|e| e.to_string())?
-        .parent()
-        .ok_or_else(|| "Could not get executable directory".to_string())
-        .map(|p| p.to_path_buf())
+#[tauri::command]
+async fn get_settings(settings: State<'_, SettingsState>) -> Result<Settings, String> {
+    Ok(settings.0.lock().get().clone())
 }
 
-// Add this function near the top of the file, after the imports
-fn check_required_env_vars() -> Result<(), String> {
-    let required_vars = [
-        ("GITHUB_CLIENT_ID", "GitHub Client ID"),
-        ("GITHUB_CLIENT_SECRET", "GitHub Client Secret")
-    ];
-
-    let mut missing_vars = Vec::new();
-
-    for (var, display_name) in required_vars.iter() {
-        if std::env::var(var).is_err() {
-            missing_vars.push(*display_name);
-        }
-    }
+#[tauri::command]
+async fn update_settings(
+    app_handle: tauri::AppHandle,
+    new_settings: Settings,
+    settings: State<'_, SettingsState>,
+    auth_state: State<'_, AuthState>,
+    accounts: State<'_, AccountsState>,
+) -> Result<Settings, String> {
+    apply_settings(&app_handle, &settings, &auth_state, &accounts, new_settings)
+}
 
-    if !missing_vars.is_empty() {
-        let os_specific_instructions = if cfg!(target_os = "windows") {
-            "1. Open System Properties (Win + Pause/Break)\n\
-             2. Click 'Environment Variables'\n\
-             3. Under 'User variables', click 'New'\n\
-             4. Add each missing variable"
-        } else if cfg!(target_os = "macos") {
-            "1. Open Terminal\n\
-             2. Edit ~/.zshrc or ~/.bash_profile\n\
-             3. Add: export VARIABLE_NAME=value\n\
-             4. Run: source ~/.zshrc (or ~/.bash_profile)"
-        } else {
-            "1. Open Terminal\n\
-             2. Edit ~/.profile or ~/.bashrc\n\
-             3. Add: export VARIABLE_NAME=value\n\
-             4. Run: source ~/.profile (or ~/.bashrc)"
-        };
+#[tauri::command]
+async fn get_clone_directory(settings: State<'_, SettingsState>) -> Result<String, String> {
+    Ok(settings.0.lock().get().clone_directory().to_string_lossy().into_owned())
+}
 
-        let error_message = format!(
-            "Missing required environment variables:\n\n{}\n\n\
-            How to set environment variables on your system:\n\n{}\n\n\
-            After setting variables, restart the application.",
-            missing_vars.join("\n"),
-            os_specific_instructions
-        );
-        return Err(error_message);
-    }
+#[tauri::command]
+async fn set_clone_directory(
+    app_handle: tauri::AppHandle,
+    path: String,
+    settings: State<'_, SettingsState>,
+    auth_state: State<'_, AuthState>,
+    accounts: State<'_, AccountsState>,
+) -> Result<(), String> {
+    std::fs::create_dir_all(&path)
+        .map_err(|e| format!("Failed to create directory: {}", e))?;
 
+    let mut new_settings = settings.0.lock().get().clone();
+    new_settings.clone_directory = Some(path);
+    apply_settings(&app_handle, &settings, &auth_state, &accounts, new_settings)?;
     Ok(())
 }
 
 #[tauri::command]
-async fn stage_changes(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn stage_changes(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.stage_changes()
             .map_err(|e| e.to_string())?;
         Ok("Changes staged successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn commit_changes(_path: String, message: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn commit_changes(path: String, message: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.commit_changes(&message)
             .map_err(|e| e.to_string())?;
         Ok("Changes committed successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn create_branch(_path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn create_branch(path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.create_branch(&branch_name)
             .map_err(|e| e.to_string())?;
         Ok(format!("Branch '{}' created successfully", branch_name))
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn checkout_branch(_path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn checkout_branch(path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.checkout_branch(&branch_name)
             .map_err(|e| e.to_string())?;
         Ok(format!("Switched to branch '{}'", branch_name))
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn merge_branch(_path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn merge_branch(path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.merge_branch(&branch_name)
             .map_err(|e| e.to_string())?;
         Ok(format!("Branch '{}' merged successfully", branch_name))
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn delete_branch(_path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn delete_branch(path: String, branch_name: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.delete_branch(&branch_name)
             .map_err(|e| e.to_string())?;
         Ok(format!("Branch '{}' deleted successfully", branch_name))
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn view_commit_log(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn view_commit_log(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.view_commit_log()
             .map_err(|e| e.to_string())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn amend_commit(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_mut() {
+async fn amend_commit(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.amend_commit()
             .map_err(|e| e.to_string())?;
         Ok("Commit amended successfully".into())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn get_current_branch(_path: String, state: State<'_, RepoState>) -> Result<String, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn get_current_branch(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+    state.with_repo(&path, |repo| {
         repo.get_current_branch()
             .map_err(|e| e.to_string())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn list_branches(_path: String, state: State<'_, RepoState>) -> Result<Vec<String>, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn list_branches(path: String, state: State<'_, RepoState>) -> Result<Vec<String>, String> {
+    state.with_repo(&path, |repo| {
         repo.list_branches()
             .map_err(|e| e.to_string())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn list_tags(_path: String, state: State<'_, RepoState>) -> Result<Vec<String>, String> {
-    if let Some(repo) = state.0.lock().as_ref() {
+async fn list_tags(path: String, state: State<'_, RepoState>) -> Result<Vec<String>, String> {
+    state.with_repo(&path, |repo| {
         repo.list_tags()
             .map_err(|e| e.to_string())
-    } else {
-        Err("No repository opened".into())
-    }
+    })
 }
 
 #[tauri::command]
-async fn remove_local_repository(path: String, state: State<'_, RepoState>) -> Result<String, String> {
+async fn remove_local_repository(
+    path: String,
+    state: State<'_, RepoState>,
+    repo_list: State<'_, RepoListState>,
+    accounts: State<'_, AccountsState>,
+) -> Result<String, String> {
     println!("Backend: Starting repository removal for path: {}", path);
     
     // Show confirmation dialog first
@@ -628,19 +1423,8 @@ async fn remove_local_repository(path: String, state: State<'_, RepoState>) -> R
     let path_buf = PathBuf::from(&path);
     println!("Backend: Created PathBuf: {:?}", path_buf);
     
-    // Clear Git state if this is the current repository
-    {
-        let state_lock = state.0.lock();
-        if let Some(current_repo) = state_lock.as_ref() {
-            let current_path = current_repo.get_path();
-            println!("Backend: Current repo path: {:?}", current_path);
-            if current_path == path_buf {
-                println!("Backend: Clearing current repository state");
-                drop(state_lock); // Drop the read lock before taking write lock
-                *state.0.lock() = None;
-            }
-        }
-    }
+    // Close the repository (and stop its watcher) if it's open
+    state.remove(&path);
 
     // Check if directory exists before attempting removal
     if !path_buf.exists() {
@@ -658,6 +1442,8 @@ async fn remove_local_repository(path: String, state: State<'_, RepoState>) -> R
                 return Err("Failed to verify repository removal".into());
             }
             println!("Backend: Repository removed successfully");
+            repo_list.0.lock().remove(&path).map_err(|e| e.to_string())?;
+            accounts.0.lock().forget_repository(&path).map_err(|e| e.to_string())?;
             Ok("Repository removed successfully".into())
         }
         Err(e) => {
@@ -711,23 +1497,66 @@ async fn minimize_to_tray(window: tauri::Window) -> Result<(), String> {
     window.hide().map_err(|e| e.to_string())
 }
 
-fn main() {
-    // Load environment variables first
-    dotenv::dotenv().ok();
-
-    // Check required environment variables
-    if let Err(error_message) = check_required_env_vars() {
-        // Create a simple message dialog using native message box
-        tauri::api::dialog::blocking::message(
-            None::<&tauri::Window>,
-            "Configuration Error",
-            error_message
-        );
-        std::process::exit(1);
+// Loads settings.json (importing the old .env/clone_directory.txt setup on
+// first run) and makes sure the GitHub OAuth app is configured
+fn load_settings<A: tauri::Assets>(
+    context: &tauri::Context<A>,
+    credentials: Arc<dyn CredentialStore>,
+) -> Result<SettingsStore, String> {
+    let config_dir = tauri::api::path::app_config_dir(context.config())
+        .ok_or_else(|| "Could not find config directory".to_string())?;
+    let store = SettingsStore::load(&config_dir, credentials).map_err(|e| e.to_string())?;
+
+    let missing = store.get().missing_github_settings();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Missing required settings:\n\n{}\n\n\
+            Add them to {} and restart the application.",
+            missing.join("\n"),
+            store.path().display()
+        ));
     }
+    Ok(store)
+}
 
+fn open_credentials<A: tauri::Assets>(context: &tauri::Context<A>) -> Result<Arc<dyn CredentialStore>, String> {
+    let data_dir = tauri::api::path::app_data_dir(context.config())
+        .ok_or_else(|| "Could not find app data directory".to_string())?;
+    let store = credentials::open_default(&data_dir).map_err(|e| e.to_string())?;
+    eprintln!("Storing credentials in: {}", store.backend());
+    Ok(store)
+}
+
+fn load_accounts<A: tauri::Assets>(
+    context: &tauri::Context<A>,
+    credentials: Arc<dyn CredentialStore>,
+) -> Result<AccountRegistry, String> {
+    let config_dir = tauri::api::path::app_config_dir(context.config())
+        .ok_or_else(|| "Could not find config directory".to_string())?;
+    AccountRegistry::load(&config_dir, credentials).map_err(|e| e.to_string())
+}
+
+fn main() {
     // Create the context first
-    let _context = tauri::generate_context!();
+    let context = tauri::generate_context!();
+
+    let startup = open_credentials(&context).and_then(|store| {
+        let settings = load_settings(&context, store.clone())?;
+        let accounts = load_accounts(&context, store)?;
+        Ok((settings, Arc::new(PLMutex::new(accounts))))
+    });
+    let (settings, accounts) = match startup {
+        Ok(loaded) => loaded,
+        Err(error_message) => {
+            // Create a simple message dialog using native message box
+            tauri::api::dialog::blocking::message(
+                None::<&tauri::Window>,
+                "Configuration Error",
+                error_message
+            );
+            std::process::exit(1);
+        }
+    };
     
     // Load saved taskbar state from app config
     let window_state = WindowState {
@@ -746,7 +1575,12 @@ fn main() {
     
     let app = tauri::Builder::default()
         .manage(RepoState::new())
-        .manage(AuthState::new())
+        .manage(AuthState::new(settings.get(), accounts.clone()))
+        .manage(AccountsState(accounts))
+        .manage(SettingsState(Arc::new(PLMutex::new(settings))))
+        .manage(SearchState::default())
+        .manage(PassphraseState::default())
+        .manage(HostKeyState::default())
         .manage(window_state)
         .system_tray(tray_menu)
         .on_system_tray_event(|app, event| match event {
@@ -780,14 +1614,28 @@ fn main() {
         })
         .invoke_handler(tauri::generate_handler![
             open_repository,
+            close_repository,
+            list_open_repositories,
+            list_known_repositories,
+            list_repository_groups,
+            set_repository_favourite,
+            set_repository_groups,
+            forget_repository,
+            prune_missing_repositories,
+            scan_for_repositories,
+            import_repositories,
             push_changes,
             push_changes_remote,
             revert_commit,
             revert_commit_remote,
             github_auth,
-            handle_auth_callback,
+            github_device_auth,
             list_github_repos,
+            list_hosted_repositories,
+            get_hosted_repository,
+            list_pull_requests,
             get_repository_stats,
+            get_status,
             github_logout,
             github_cancel_auth,
             check_auth_status,
@@ -795,6 +1643,21 @@ fn main() {
             pull_changes,
             clone_repository,
             set_github_token,
+            check_stored_token,
+            get_github_web_url,
+            list_github_accounts,
+            set_default_github_account,
+            bind_repository_account,
+            get_repository_account,
+            list_repository_account_bindings,
+            list_ssh_keys,
+            generate_ssh_key,
+            get_ssh_public_key,
+            upload_ssh_key,
+            set_host_ssh_key,
+            answer_ssh_passphrase,
+            answer_ssh_host_key,
+            trust_tls_certificate,
             cleanup_before_close,
             validate_github_token,
             stash_changes,
@@ -803,9 +1666,26 @@ fn main() {
             reset_hard,
             list_remotes,
             view_diff,
+            diff_revisions,
+            get_binary_diff_assets,
+            export_patches,
+            export_working_diff,
+            apply_patch,
+            check_patch,
+            search_code,
+            search_history,
+            cancel_search,
             get_repository_settings,
+            blame_file,
+            file_history,
+            list_tree,
+            read_file_at_revision,
+            save_file_at_revision,
             get_home_dir,
             set_clone_directory,
+            get_clone_directory,
+            get_settings,
+            update_settings,
             stage_changes,
             commit_changes,
             create_branch,
@@ -823,6 +1703,17 @@ fn main() {
             minimize_to_tray,
         ])
         .setup(|app| {
+            let config_dir = app.path_resolver()
+                .app_config_dir()
+                .ok_or("Could not find config directory")?;
+            let repo_list = RepoList::load(&config_dir)?;
+            app.manage(RepoListState(Arc::new(PLMutex::new(repo_list))));
+
+            // Assets exported by the previous run; nothing shows them any more
+            if let Ok(assets_dir) = diff_assets_dir(&app.handle()) {
+                let _ = std::fs::remove_dir_all(assets_dir);
+            }
+
             let window = app.get_window("main").unwrap();
             window.set_decorations(true).unwrap();
             window.set_skip_taskbar(true).unwrap();
@@ -836,7 +1727,7 @@ fn main() {
                 api.prevent_close();
             }
         })
-        .build(tauri::generate_context!())
+        .build(context)
         .expect("error while running tauri application");
 
     app.run(|_app_handle, event| match event {
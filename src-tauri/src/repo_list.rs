@@ -0,0 +1,254 @@
+use git2::Repository;
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git_commands::GitError;
+
+const REPO_LIST_FILE: &str = "repositories.json";
+
+// How deep `scan` descends below the chosen directory by default
+pub const DEFAULT_SCAN_DEPTH: usize = 4;
+
+// Directories that never contain repositories worth importing and can be huge
+const SKIPPED_SCAN_DIRS: &[&str] = &["node_modules", "target", "vendor", "build", "dist"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownRepository {
+    pub path: String,
+    pub name: String,
+    // Unix timestamp in seconds, None if only imported and never opened
+    pub last_opened: Option<i64>,
+    #[serde(default)]
+    pub favourite: bool,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // Recomputed on every listing, whatever was saved is ignored
+    #[serde(skip_deserializing, default)]
+    pub missing: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RepoListFile {
+    repositories: Vec<KnownRepository>,
+}
+
+// Local repositories the user has opened, cloned or imported, persisted in
+// the app config dir so they survive restarts
+pub struct RepoList {
+    file: PathBuf,
+    repositories: Vec<KnownRepository>,
+}
+
+impl RepoList {
+    pub fn load(config_dir: &Path) -> Result<Self, GitError> {
+        let file = config_dir.join(REPO_LIST_FILE);
+        let repositories = if file.exists() {
+            let content = fs::read_to_string(&file)?;
+            match serde_json::from_str::<RepoListFile>(&content) {
+                Ok(list) => list.repositories,
+                Err(e) => {
+                    // Keep the unreadable file around instead of overwriting it on the next save
+                    eprintln!("Failed to parse {}, starting with an empty list: {}", REPO_LIST_FILE, e);
+                    fs::rename(&file, file.with_extension("json.bak"))?;
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(RepoList { file, repositories })
+    }
+
+    fn save(&self) -> Result<(), GitError> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(&RepoListFile { repositories: self.repositories.clone() })
+            .map_err(|e| GitError::Custom(e.to_string()))?;
+
+        // Write then rename so a crash mid-write can't truncate the list
+        let tmp = self.file.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+
+    // Favourites first, then most recently opened; repos no longer on disk are
+    // flagged as missing rather than dropped so the user can decide
+    pub fn list(&self) -> Vec<KnownRepository> {
+        let mut repositories: Vec<KnownRepository> = self.repositories.iter()
+            .cloned()
+            .map(|mut repo| {
+                repo.missing = !is_repository(Path::new(&repo.path));
+                repo
+            })
+            .collect();
+        repositories.sort_by(|a, b| {
+            b.favourite.cmp(&a.favourite)
+                .then(b.last_opened.cmp(&a.last_opened))
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        repositories
+    }
+
+    pub fn record_opened(&mut self, path: &str) -> Result<(), GitError> {
+        let now = unix_now();
+        let path = normalize(path);
+        match self.find_mut(&path) {
+            Some(repo) => repo.last_opened = Some(now),
+            None => self.repositories.push(new_entry(&path, Some(now))),
+        }
+        self.save()
+    }
+
+    pub fn set_favourite(&mut self, path: &str, favourite: bool) -> Result<(), GitError> {
+        self.find_mut(&normalize(path))
+            .ok_or_else(|| GitError::Custom(format!("Unknown repository: {}", path)))?
+            .favourite = favourite;
+        self.save()
+    }
+
+    pub fn set_groups(&mut self, path: &str, groups: Vec<String>) -> Result<(), GitError> {
+        let mut groups: Vec<String> = groups.into_iter()
+            .map(|group| group.trim().to_string())
+            .filter(|group| !group.is_empty())
+            .collect();
+        groups.sort();
+        groups.dedup();
+
+        self.find_mut(&normalize(path))
+            .ok_or_else(|| GitError::Custom(format!("Unknown repository: {}", path)))?
+            .groups = groups;
+        self.save()
+    }
+
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups: Vec<String> = self.repositories.iter()
+            .flat_map(|repo| repo.groups.iter().cloned())
+            .collect();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+
+    pub fn remove(&mut self, path: &str) -> Result<(), GitError> {
+        let path = normalize(path);
+        self.repositories.retain(|repo| repo.path != path);
+        self.save()
+    }
+
+    // Forgets every repository that no longer exists on disk and returns their paths
+    pub fn prune_missing(&mut self) -> Result<Vec<String>, GitError> {
+        let (missing, kept): (Vec<_>, Vec<_>) = self.repositories.drain(..)
+            .partition(|repo| !is_repository(Path::new(&repo.path)));
+        self.repositories = kept;
+        self.save()?;
+        Ok(missing.into_iter().map(|repo| repo.path).collect())
+    }
+
+    // Adds repositories found by `scan`; already known paths are left alone.
+    // Returns how many were added.
+    pub fn import(&mut self, paths: &[String]) -> Result<usize, GitError> {
+        let mut added = 0;
+        for path in paths {
+            let path = normalize(path);
+            if !is_repository(Path::new(&path)) {
+                return Err(GitError::Custom(format!("Not a git repository: {}", path)));
+            }
+            if self.find_mut(&path).is_none() {
+                self.repositories.push(new_entry(&path, None));
+                added += 1;
+            }
+        }
+        self.save()?;
+        Ok(added)
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        let path = normalize(path);
+        self.repositories.iter().any(|repo| repo.path == path)
+    }
+
+    fn find_mut(&mut self, path: &str) -> Option<&mut KnownRepository> {
+        self.repositories.iter_mut().find(|repo| repo.path == path)
+    }
+}
+
+// Looks for repositories below `root` without descending into repositories,
+// hidden directories or build output. Runs without the list locked since
+// large trees can take a while.
+pub fn scan(root: &Path, max_depth: usize) -> Vec<String> {
+    let mut found = Vec::new();
+    scan_dir(root, max_depth, &mut found);
+    found.into_iter()
+        .map(|path| normalize(&path.to_string_lossy()))
+        .collect()
+}
+
+fn scan_dir(dir: &Path, depth_left: usize, found: &mut Vec<PathBuf>) {
+    if dir.join(".git").exists() && is_repository(dir) {
+        found.push(dir.to_path_buf());
+        return;
+    }
+    if depth_left == 0 {
+        return;
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut subdirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map_or(false, |kind| kind.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !SKIPPED_SCAN_DIRS.contains(&name.as_ref())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    subdirs.sort();
+
+    for subdir in subdirs {
+        scan_dir(&subdir, depth_left - 1, found);
+    }
+}
+
+fn is_repository(path: &Path) -> bool {
+    Repository::open(path).is_ok()
+}
+
+// Drops trailing separators so "repo" and "repo/" are the same entry
+pub fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches(&['/', '\\'][..]);
+    if trimmed.is_empty() || trimmed.ends_with(':') {
+        path.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn new_entry(path: &str, last_opened: Option<i64>) -> KnownRepository {
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string());
+    KnownRepository {
+        path: path.to_string(),
+        name,
+        last_opened,
+        favourite: false,
+        groups: Vec::new(),
+        missing: false,
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
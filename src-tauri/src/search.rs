@@ -0,0 +1,369 @@
+use git2::{Oid, Repository, TreeWalkMode, TreeWalkResult};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::git_commands::{GitError, DEFAULT_MAX_FILE_BYTES};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CodeSearchOptions {
+    pub pattern: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeMatch {
+    pub path: String,
+    pub line_number: usize,
+    pub line: String,
+    // Ranges of every match within the line, in UTF-16 code units like the
+    // diff spans
+    pub ranges: Vec<MatchRange>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PickaxeMode {
+    // `git log -S`: the number of occurrences in a file changed
+    #[default]
+    Occurrences,
+    // `git log -G`: an added or removed line matches
+    ChangedLines,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistorySearchOptions {
+    pub pattern: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub mode: PickaxeMode,
+    pub rev: Option<String>,
+    pub paths: Vec<String>,
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryLineMatch {
+    pub path: String,
+    pub origin: char,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryMatch {
+    pub commit_id: String,
+    pub summary: String,
+    pub author: String,
+    pub date: i64,
+    pub files: Vec<String>,
+    // Matching added/removed lines, only collected in ChangedLines mode
+    pub lines: Vec<HistoryLineMatch>,
+}
+
+// Payload of the events streamed while a history search runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistorySearchEvent {
+    pub search_id: String,
+    pub result: HistoryMatch,
+}
+
+fn build_regex(pattern: &str, is_regex: bool, case_sensitive: bool) -> Result<Regex, GitError> {
+    if pattern.is_empty() {
+        return Err(GitError::Custom("Search pattern is empty".into()));
+    }
+    let pattern = if is_regex { pattern.to_string() } else { regex::escape(pattern) };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(|e| GitError::Custom(format!("Invalid search pattern: {}", e)))
+}
+
+fn build_globs(globs: &[String]) -> Result<Option<GlobSet>, GitError> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|e| GitError::Custom(format!("Invalid glob '{}': {}", glob, e)))?);
+    }
+    builder.build()
+        .map(Some)
+        .map_err(|e| GitError::Custom(e.to_string()))
+}
+
+fn looks_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+// Searches every file in the tree at `rev` without touching the working directory
+pub fn search_code(repo: &Repository, rev: &str, options: &CodeSearchOptions) -> Result<Vec<CodeMatch>, GitError> {
+    let regex = build_regex(&options.pattern, options.regex, options.case_sensitive)?;
+    let include = build_globs(&options.include_globs)?;
+    let exclude = build_globs(&options.exclude_globs)?;
+    let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+
+    let mut blobs: Vec<(String, Oid)> = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() != Some(git2::ObjectType::Blob) {
+            return TreeWalkResult::Ok;
+        }
+        if let Some(name) = entry.name() {
+            let path = format!("{}{}", root, name);
+            let included = include.as_ref().map_or(true, |globs| globs.is_match(&path));
+            let excluded = exclude.as_ref().map_or(false, |globs| globs.is_match(&path));
+            if included && !excluded {
+                blobs.push((path, entry.id()));
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    let max_results = options.max_results.unwrap_or(usize::MAX);
+    let mut matches = Vec::new();
+
+    // Sizes come from the object headers so large blobs are never loaded,
+    // and an object that can't be read only drops that file
+    let odb = repo.odb()?;
+    'files: for (path, id) in blobs {
+        match odb.read_header(id) {
+            Ok((size, _)) if size <= DEFAULT_MAX_FILE_BYTES => {}
+            _ => continue,
+        }
+        let blob = match repo.find_blob(id) {
+            Ok(blob) => blob,
+            Err(_) => continue,
+        };
+        if looks_binary(blob.content()) {
+            continue;
+        }
+
+        let text = String::from_utf8_lossy(blob.content());
+        for (idx, line) in text.lines().enumerate() {
+            let ranges: Vec<MatchRange> = regex.find_iter(line)
+                .map(|m| MatchRange {
+                    start: line[..m.start()].encode_utf16().count(),
+                    end: line[..m.end()].encode_utf16().count(),
+                })
+                .collect();
+            if ranges.is_empty() {
+                continue;
+            }
+
+            matches.push(CodeMatch {
+                path: path.clone(),
+                line_number: idx + 1,
+                line: line.to_string(),
+                ranges,
+            });
+            if matches.len() >= max_results {
+                break 'files;
+            }
+        }
+    }
+
+    Ok(matches)
+}
+
+// Walks history looking for commits that add or remove the pattern, calling
+// `on_match` as soon as each one is found. Returns the number of matches.
+pub fn search_history(
+    repo: &Repository,
+    options: &HistorySearchOptions,
+    cancelled: &AtomicBool,
+    mut on_match: impl FnMut(HistoryMatch),
+) -> Result<usize, GitError> {
+    let regex = build_regex(&options.pattern, options.regex, options.case_sensitive)?;
+
+    let mut revwalk = repo.revwalk()?;
+    match &options.rev {
+        Some(rev) => revwalk.push(repo.revparse_single(rev)?.peel_to_commit()?.id())?,
+        None => revwalk.push_head()?,
+    }
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+    let max_results = options.max_results.unwrap_or(usize::MAX);
+    let mut found = 0;
+
+    for commit_id in revwalk {
+        if cancelled.load(Ordering::SeqCst) || found >= max_results {
+            break;
+        }
+
+        // Like `git log -S`, merges are skipped since their changes show up
+        // in the commits being merged
+        let commit = repo.find_commit(commit_id?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+
+        let mut diff_opts = git2::DiffOptions::new();
+        for path in &options.paths {
+            diff_opts.pathspec(path);
+        }
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), Some(&mut diff_opts))?;
+
+        let (files, lines) = match options.mode {
+            PickaxeMode::Occurrences => (occurrence_changes(repo, &diff, &regex), Vec::new()),
+            PickaxeMode::ChangedLines => changed_line_matches(&diff, &regex)?,
+        };
+        if files.is_empty() {
+            continue;
+        }
+
+        let author = commit.author();
+        on_match(HistoryMatch {
+            commit_id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or("").to_string(),
+            author: author.name().unwrap_or("Unknown").to_string(),
+            date: author.when().seconds(),
+            files,
+            lines,
+        });
+        found += 1;
+    }
+
+    Ok(found)
+}
+
+fn count_occurrences(repo: &Repository, id: Oid, regex: &Regex) -> usize {
+    if id.is_zero() {
+        return 0;
+    }
+    match repo.find_blob(id) {
+        Ok(blob) if !looks_binary(blob.content()) => {
+            regex.find_iter(&String::from_utf8_lossy(blob.content())).count()
+        }
+        _ => 0,
+    }
+}
+
+fn occurrence_changes(repo: &Repository, diff: &git2::Diff, regex: &Regex) -> Vec<String> {
+    diff.deltas()
+        .filter(|delta| {
+            count_occurrences(repo, delta.old_file().id(), regex)
+                != count_occurrences(repo, delta.new_file().id(), regex)
+        })
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect()
+}
+
+fn changed_line_matches(diff: &git2::Diff, regex: &Regex) -> Result<(Vec<String>, Vec<HistoryLineMatch>), GitError> {
+    let mut files: Vec<String> = Vec::new();
+    let mut lines = Vec::new();
+
+    diff.foreach(
+        &mut |_delta, _| true,
+        None,
+        None,
+        Some(&mut |delta, _hunk, line| {
+            if !matches!(line.origin(), '+' | '-') {
+                return true;
+            }
+            let content = String::from_utf8_lossy(line.content());
+            let content = content.trim_end_matches(&['\r', '\n'][..]);
+            if !regex.is_match(content) {
+                return true;
+            }
+
+            let path = delta.new_file().path()
+                .or_else(|| delta.old_file().path())
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if files.last() != Some(&path) {
+                files.push(path.clone());
+            }
+            lines.push(HistoryLineMatch {
+                path,
+                origin: line.origin(),
+                line: content.to_string(),
+            });
+            true
+        }),
+    )?;
+
+    Ok((files, lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // A repository with `files` committed on HEAD
+    fn repository(files: &[(&str, &[u8])]) -> (tempfile::TempDir, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        {
+            let mut index = repo.index().unwrap();
+            for (path, content) in files {
+                std::fs::write(dir.path().join(path), content).unwrap();
+                index.add_path(Path::new(path)).unwrap();
+            }
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+            repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+        }
+        (dir, repo)
+    }
+
+    fn options(pattern: &str) -> CodeSearchOptions {
+        CodeSearchOptions { pattern: pattern.into(), ..CodeSearchOptions::default() }
+    }
+
+    #[test]
+    fn reports_match_ranges_in_utf16_code_units() {
+        let (_dir, repo) = repository(&[("emoji.txt", "😀 é needle needle\n".as_bytes())]);
+
+        let matches = search_code(&repo, "HEAD", &options("needle")).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        let ranges: Vec<_> = matches[0].ranges.iter().map(|range| (range.start, range.end)).collect();
+        // 😀 is two UTF-16 code units, é one
+        assert_eq!(ranges, vec![(5, 11), (12, 18)]);
+    }
+
+    #[test]
+    fn skips_large_and_binary_files() {
+        let large = format!("needle\n{}", "x".repeat(DEFAULT_MAX_FILE_BYTES));
+        let (_dir, repo) = repository(&[
+            ("large.txt", large.as_bytes()),
+            ("binary.bin", b"needle\0"),
+            ("small.txt", b"needle\n"),
+        ]);
+
+        let matches = search_code(&repo, "HEAD", &options("needle")).unwrap();
+
+        let paths: Vec<_> = matches.iter().map(|m| m.path.as_str()).collect();
+        assert_eq!(paths, vec!["small.txt"]);
+    }
+
+    #[test]
+    fn skips_objects_that_cannot_be_read() {
+        let (dir, repo) = repository(&[("lost.txt", b"needle lost\n"), ("kept.txt", b"needle kept\n")]);
+        let lost = repo.revparse_single("HEAD:lost.txt").unwrap().id().to_string();
+        std::fs::remove_file(dir.path().join(".git/objects").join(&lost[..2]).join(&lost[2..])).unwrap();
+
+        let matches = search_code(&repo, "HEAD", &options("needle")).unwrap();
+
+        let paths: Vec<_> = matches.iter().map(|m| m.path.as_str()).collect();
+        assert_eq!(paths, vec!["kept.txt"]);
+    }
+}
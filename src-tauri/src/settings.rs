@@ -0,0 +1,408 @@
+use serde::{Serialize, Serializer, Deserialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::credentials::CredentialStore;
+use crate::git_commands::GitError;
+use crate::hosting::{Host, ProviderKind};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+// Files and variables that held configuration before settings.json existed
+const LEGACY_CLONE_DIRECTORY_FILE: &str = "clone_directory.txt";
+const LEGACY_ENV_FILE: &str = ".env";
+const LEGACY_ENV_VARS: &[&str] = &["CLONE_DIRECTORY", "GITHUB_CLIENT_ID", "GITHUB_CLIENT_SECRET"];
+
+// Bump together with a new entry in MIGRATIONS
+pub const SETTINGS_VERSION: u32 = 1;
+
+// MIGRATIONS[n] upgrades a version n document to version n + 1
+const MIGRATIONS: &[fn(&mut Value)] = &[migrate_v0_to_v1];
+
+// An OAuth app registered on one host
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OAuthAppSettings {
+    // Hostname or base URL of the instance; None is the provider's public
+    // one (github.com, gitlab.com, gitea.com)
+    pub host: Option<String>,
+    // API base URL, only needed if it isn't the provider's usual path
+    // (<host>/api/v3, /api/v4 or /api/v1)
+    pub api_url: Option<String>,
+    pub client_id: Option<String>,
+    // Optional; without it sign-in uses the device flow. Kept in the
+    // credential store and written out, to settings.json as well as to the
+    // webview, only as `has_client_secret`. Coming from the webview, None
+    // keeps the stored secret and an empty string removes it.
+    #[serde(rename(serialize = "has_client_secret"), serialize_with = "serialize_is_some")]
+    pub client_secret: Option<String>,
+}
+
+fn serialize_is_some<S: Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(secret.is_some())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshSettings {
+    // Private keys offered after the SSH agent; empty tries
+    // ~/.ssh/id_ed25519, id_ecdsa and id_rsa
+    pub key_files: Vec<String>,
+    // Hostname -> private key to use for remotes on that host, e.g. to keep
+    // a work and a personal GitHub account apart
+    pub host_keys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsSettings {
+    // Hostname -> SHA-256 fingerprint of a certificate to accept even though
+    // it doesn't validate, e.g. a self-signed one on an internal server
+    pub trusted_certificates: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    // Where new clones go; None means ~/.simplegit
+    pub clone_directory: Option<String>,
+    pub github: OAuthAppSettings,
+    // OAuth apps on GitHub Enterprise Server instances, one per host
+    pub github_enterprise: Vec<OAuthAppSettings>,
+    // OAuth apps on gitlab.com or self-managed GitLab, one per host
+    pub gitlab: Vec<OAuthAppSettings>,
+    // OAuth apps on Gitea or Forgejo servers, one per host
+    pub gitea: Vec<OAuthAppSettings>,
+    pub ssh: SshSettings,
+    pub tls: TlsSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            clone_directory: None,
+            github: OAuthAppSettings::default(),
+            github_enterprise: Vec::new(),
+            gitlab: Vec::new(),
+            gitea: Vec::new(),
+            ssh: SshSettings::default(),
+            tls: TlsSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn clone_directory(&self) -> PathBuf {
+        match &self.clone_directory {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::home_dir()
+                .unwrap_or_default()
+                .join(".simplegit"),
+        }
+    }
+
+    // Every OAuth app with its provider, github.com's included even if unset
+    pub fn oauth_apps(&self) -> Vec<(&OAuthAppSettings, ProviderKind)> {
+        let mut apps = vec![(&self.github, ProviderKind::GitHub)];
+        apps.extend(self.github_enterprise.iter().map(|app| (app, ProviderKind::GitHub)));
+        apps.extend(self.gitlab.iter().map(|app| (app, ProviderKind::GitLab)));
+        apps.extend(self.gitea.iter().map(|app| (app, ProviderKind::Gitea)));
+        apps
+    }
+
+    fn oauth_apps_mut(&mut self) -> Vec<(&mut OAuthAppSettings, ProviderKind)> {
+        let mut apps = vec![(&mut self.github, ProviderKind::GitHub)];
+        apps.extend(self.github_enterprise.iter_mut().map(|app| (app, ProviderKind::GitHub)));
+        apps.extend(self.gitlab.iter_mut().map(|app| (app, ProviderKind::GitLab)));
+        apps.extend(self.gitea.iter_mut().map(|app| (app, ProviderKind::Gitea)));
+        apps
+    }
+
+    // Sign-in needs an OAuth app on at least one host
+    pub fn missing_github_settings(&self) -> Vec<&'static str> {
+        let has_client_id = |app: &OAuthAppSettings| app.client_id.as_deref().is_some_and(|id| !id.is_empty());
+        let mut missing = Vec::new();
+        if !has_client_id(&self.github)
+            && !self.github_enterprise.iter()
+                .chain(&self.gitlab)
+                .chain(&self.gitea)
+                .any(has_client_id)
+        {
+            missing.push("github.client_id");
+        }
+        missing
+    }
+}
+
+// Credential store key of the client secret of the OAuth app on a host
+fn client_secret_key(app: &OAuthAppSettings, provider: ProviderKind) -> String {
+    format!("oauth_client_secret:{}", Host::new(provider, app.host.as_deref(), None).web_url)
+}
+
+// Owns settings.json: loads and migrates it, and writes every change back.
+// Client secrets go to the credential store instead.
+pub struct SettingsStore {
+    file: PathBuf,
+    settings: Settings,
+    credentials: Arc<dyn CredentialStore>,
+}
+
+impl SettingsStore {
+    pub fn load(config_dir: &Path, credentials: Arc<dyn CredentialStore>) -> Result<Self, GitError> {
+        let file = config_dir.join(SETTINGS_FILE);
+
+        let (document, imported_legacy) = if file.exists() {
+            let content = fs::read_to_string(&file)?;
+            let document = serde_json::from_str::<Value>(&content)
+                .map_err(|e| GitError::Custom(format!("Failed to parse {}: {}", file.display(), e)))?;
+            (document, false)
+        } else {
+            (legacy_document(config_dir), true)
+        };
+
+        let from_version = document_version(&document);
+        if from_version > SETTINGS_VERSION {
+            return Err(GitError::Custom(format!(
+                "{} was written by a newer version of SimpleGit (settings version {})",
+                file.display(),
+                from_version
+            )));
+        }
+
+        let mut document = document;
+        for migration in &MIGRATIONS[from_version as usize..] {
+            migration(&mut document);
+        }
+        let mut settings: Settings = serde_json::from_value(document)
+            .map_err(|e| GitError::Custom(format!("Invalid settings: {}", e)))?;
+
+        // Secrets still in the file (from .env or an older version) move to
+        // the credential store; the others are read from there
+        let mut moved_secrets = false;
+        for (app, provider) in settings.oauth_apps_mut() {
+            let key = client_secret_key(app, provider);
+            match app.client_secret.as_deref().filter(|secret| !secret.is_empty()) {
+                Some(secret) => {
+                    credentials.set(&key, secret).map_err(|e| GitError::Custom(e.to_string()))?;
+                    moved_secrets = true;
+                }
+                None => app.client_secret = credentials.get(&key).map_err(|e| GitError::Custom(e.to_string()))?,
+            }
+        }
+
+        let store = SettingsStore { file, settings, credentials };
+        if imported_legacy || moved_secrets || from_version < SETTINGS_VERSION {
+            store.save()?;
+        }
+        if imported_legacy {
+            let _ = fs::remove_file(config_dir.join(LEGACY_CLONE_DIRECTORY_FILE));
+        }
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn get(&self) -> &Settings {
+        &self.settings
+    }
+
+    // Replaces the settings and saves them; returns false if nothing changed.
+    // Apps without a client_secret keep the one stored for their host.
+    pub fn set(&mut self, mut settings: Settings) -> Result<bool, GitError> {
+        settings.version = SETTINGS_VERSION;
+        let stored: BTreeMap<String, String> = self.settings.oauth_apps().into_iter()
+            .filter_map(|(app, provider)| Some((client_secret_key(app, provider), app.client_secret.clone()?)))
+            .collect();
+        let mut secrets = BTreeMap::new();
+        for (app, provider) in settings.oauth_apps_mut() {
+            let key = client_secret_key(app, provider);
+            app.client_secret = match app.client_secret.take() {
+                None => stored.get(&key).cloned(),
+                Some(secret) => Some(secret).filter(|secret| !secret.is_empty()),
+            };
+            secrets.insert(key, app.client_secret.clone());
+        }
+        if settings == self.settings {
+            return Ok(false);
+        }
+
+        for (key, secret) in &secrets {
+            let result = match secret {
+                Some(secret) if stored.get(key) != Some(secret) => self.credentials.set(key, secret),
+                None if stored.contains_key(key) => self.credentials.delete(key),
+                _ => Ok(()),
+            };
+            result.map_err(|e| GitError::Custom(e.to_string()))?;
+        }
+        // Apps that were removed altogether
+        for key in stored.keys().filter(|key| !secrets.contains_key(*key)) {
+            self.credentials.delete(key).map_err(|e| GitError::Custom(e.to_string()))?;
+        }
+
+        let previous = std::mem::replace(&mut self.settings, settings);
+        if let Err(e) = self.save() {
+            self.settings = previous;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<(), GitError> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(&self.settings)
+            .map_err(|e| GitError::Custom(e.to_string()))?;
+
+        let tmp = self.file.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+}
+
+fn document_version(document: &Value) -> u32 {
+    document.get("version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32
+}
+
+// Version 0 is the flat, env-style configuration used before settings.json:
+// clone_directory.txt, a .env file and process environment variables
+fn legacy_document(config_dir: &Path) -> Value {
+    let mut document = serde_json::Map::new();
+
+    for (key, value) in read_env_file(Path::new(LEGACY_ENV_FILE)) {
+        if LEGACY_ENV_VARS.contains(&key.as_str()) {
+            document.insert(key, Value::String(value));
+        }
+    }
+    for key in LEGACY_ENV_VARS {
+        if let Ok(value) = std::env::var(key) {
+            document.insert(key.to_string(), Value::String(value));
+        }
+    }
+    if let Ok(dir) = fs::read_to_string(config_dir.join(LEGACY_CLONE_DIRECTORY_FILE)) {
+        document.insert("CLONE_DIRECTORY".into(), Value::String(dir.trim().to_string()));
+    }
+
+    Value::Object(document)
+}
+
+fn read_env_file(path: &Path) -> Vec<(String, String)> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let key = key.trim().trim_start_matches("export ").trim();
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+fn migrate_v0_to_v1(document: &mut Value) {
+    let take = |document: &mut Value, key: &str| {
+        document.as_object_mut()
+            .and_then(|object| object.remove(key))
+            .filter(|value| value.as_str().is_some_and(|s| !s.is_empty()))
+    };
+    let clone_directory = take(document, "CLONE_DIRECTORY");
+    let client_id = take(document, "GITHUB_CLIENT_ID");
+    let client_secret = take(document, "GITHUB_CLIENT_SECRET");
+
+    *document = json!({
+        "version": 1,
+        "clone_directory": clone_directory,
+        "github": {
+            "client_id": client_id,
+            "client_secret": client_secret,
+        },
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::EncryptedFileStore;
+
+    fn credentials(dir: &Path) -> Arc<dyn CredentialStore> {
+        Arc::new(EncryptedFileStore::with_key(dir.join("credentials.vault"), [7; 32]))
+    }
+
+    fn app(host: Option<&str>, client_secret: Option<&str>) -> OAuthAppSettings {
+        OAuthAppSettings {
+            host: host.map(String::from),
+            client_id: Some("client-id".into()),
+            client_secret: client_secret.map(String::from),
+            ..OAuthAppSettings::default()
+        }
+    }
+
+    #[test]
+    fn moves_client_secrets_out_of_settings_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let document = json!({
+            "version": 1,
+            "github": { "client_id": "client-id", "client_secret": "github-secret" },
+            "gitea": [{ "host": "git.example.com", "client_id": "client-id" }],
+        });
+        fs::write(dir.path().join(SETTINGS_FILE), document.to_string()).unwrap();
+        let store = credentials(dir.path());
+
+        let settings = SettingsStore::load(dir.path(), store.clone()).unwrap();
+
+        assert_eq!(settings.get().github.client_secret.as_deref(), Some("github-secret"));
+        assert_eq!(store.get("oauth_client_secret:https://github.com").unwrap().as_deref(), Some("github-secret"));
+        let saved = fs::read_to_string(dir.path().join(SETTINGS_FILE)).unwrap();
+        assert!(!saved.contains("github-secret"));
+        let saved: Value = serde_json::from_str(&saved).unwrap();
+        assert_eq!(saved["github"]["has_client_secret"], json!(true));
+        assert_eq!(saved["gitea"][0]["has_client_secret"], json!(false));
+
+        // Read back from the credential store on the next start
+        let settings = SettingsStore::load(dir.path(), store).unwrap();
+        assert_eq!(settings.get().github.client_secret.as_deref(), Some("github-secret"));
+    }
+
+    #[test]
+    fn updates_keep_replace_or_remove_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = credentials(dir.path());
+        let mut settings = SettingsStore::load(dir.path(), store.clone()).unwrap();
+        let update = Settings {
+            gitlab: vec![app(None, Some("gitlab-secret")), app(Some("gitlab.example.com"), Some("other-secret"))],
+            ..Settings::default()
+        };
+        assert!(settings.set(update).unwrap());
+        let gitlab_key = "oauth_client_secret:https://gitlab.com";
+        assert_eq!(store.get(gitlab_key).unwrap().as_deref(), Some("gitlab-secret"));
+
+        // What the webview sends back has no secrets
+        let mut update: Settings = serde_json::from_value(serde_json::to_value(settings.get()).unwrap()).unwrap();
+        assert_eq!(update.gitlab[0].client_secret, None);
+        update.clone_directory = Some("/tmp/clones".into());
+        assert!(settings.set(update.clone()).unwrap());
+        assert_eq!(settings.get().gitlab[0].client_secret.as_deref(), Some("gitlab-secret"));
+        assert!(!settings.set(update.clone()).unwrap());
+
+        update.gitlab[0].client_secret = Some(String::new());
+        update.gitlab.truncate(1);
+        assert!(settings.set(update).unwrap());
+        assert_eq!(settings.get().gitlab[0].client_secret, None);
+        assert_eq!(store.get(gitlab_key).unwrap(), None);
+        assert_eq!(store.get("oauth_client_secret:https://gitlab.example.com").unwrap(), None);
+    }
+}
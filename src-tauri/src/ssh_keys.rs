@@ -0,0 +1,199 @@
+use aes_gcm::aead::OsRng;
+use serde::Serialize;
+use ssh_key::{Algorithm, HashAlg, LineEnding, PrivateKey, PublicKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::GitError;
+use crate::remote_auth;
+
+const DEFAULT_KEY_NAME: &str = "id_ed25519";
+
+// A key pair in ~/.ssh, described by its public half
+#[derive(Debug, Clone, Serialize)]
+pub struct SshKeyInfo {
+    pub private_key_path: String,
+    pub public_key_path: String,
+    // e.g. ssh-ed25519
+    pub algorithm: String,
+    pub comment: String,
+    // SHA256:..., as printed by ssh-keygen -l
+    pub fingerprint: String,
+    pub encrypted: bool,
+}
+
+pub fn ssh_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_default().join(".ssh")
+}
+
+// Key pairs in `dir`: every *.pub file with its private key next to it
+pub fn list_keys(dir: &Path) -> Result<Vec<SshKeyInfo>, GitError> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut keys: Vec<SshKeyInfo> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pub"))
+        .map(|public_key| public_key.with_extension(""))
+        .filter(|private_key| private_key.is_file())
+        .filter_map(|private_key| key_info(&private_key).ok())
+        .collect();
+    keys.sort_by(|a, b| a.private_key_path.cmp(&b.private_key_path));
+    Ok(keys)
+}
+
+pub fn key_info(private_key: &Path) -> Result<SshKeyInfo, GitError> {
+    let public_key_path = remote_auth::public_key_path(private_key);
+    let public_key = PublicKey::read_openssh_file(&public_key_path)
+        .map_err(|e| GitError::Custom(format!("Failed to read {}: {}", public_key_path.display(), e)))?;
+    Ok(SshKeyInfo {
+        private_key_path: private_key.to_string_lossy().into_owned(),
+        public_key_path: public_key_path.to_string_lossy().into_owned(),
+        algorithm: public_key.algorithm().to_string(),
+        comment: public_key.comment().to_string(),
+        fingerprint: public_key.fingerprint(HashAlg::Sha256).to_string(),
+        encrypted: remote_auth::key_is_encrypted(private_key).unwrap_or(false),
+    })
+}
+
+// Writes a new ed25519 key pair to `dir`, named id_ed25519 unless `name`
+// says otherwise. Existing keys are never overwritten.
+pub fn generate_ed25519(
+    dir: &Path,
+    name: Option<&str>,
+    comment: &str,
+    passphrase: Option<&str>,
+) -> Result<SshKeyInfo, GitError> {
+    let name = name.map(str::trim).filter(|name| !name.is_empty()).unwrap_or(DEFAULT_KEY_NAME);
+    if name.contains(['/', '\\']) || name.starts_with('.') || name.ends_with(".pub") {
+        return Err(GitError::Custom(format!("Invalid key name: {}", name)));
+    }
+    let private_key_path = dir.join(name);
+    let public_key_path = remote_auth::public_key_path(&private_key_path);
+    if private_key_path.exists() || public_key_path.exists() {
+        return Err(GitError::Custom(format!("{} already exists", private_key_path.display())));
+    }
+
+    let key_error = |e: ssh_key::Error| GitError::Custom(format!("Failed to generate the key: {}", e));
+    let mut key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).map_err(key_error)?;
+    key.set_comment(comment);
+    let public_key = key.public_key().to_openssh().map_err(key_error)?;
+    if let Some(passphrase) = passphrase.filter(|passphrase| !passphrase.is_empty()) {
+        key = key.encrypt(&mut OsRng, passphrase).map_err(key_error)?;
+    }
+
+    create_ssh_dir(dir)?;
+    // Written with 0600 permissions on Unix
+    key.write_openssh_file(&private_key_path, LineEnding::LF).map_err(key_error)?;
+    fs::write(&public_key_path, format!("{}\n", public_key))?;
+    key_info(&private_key_path)
+}
+
+// The OpenSSH public key line for a private or public key file
+pub fn public_key(path: &Path) -> Result<String, GitError> {
+    let public_key_path = if path.extension().is_some_and(|ext| ext == "pub") {
+        path.to_path_buf()
+    } else {
+        remote_auth::public_key_path(path)
+    };
+    let content = fs::read_to_string(&public_key_path)
+        .map_err(|e| GitError::Custom(format!("Failed to read {}: {}", public_key_path.display(), e)))?;
+    Ok(content.trim().to_string())
+}
+
+// ssh refuses a ~/.ssh that others can write to, so create it private
+pub fn create_ssh_dir(dir: &Path) -> Result<(), GitError> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_plain_and_passphrase_protected_keys() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let plain = generate_ed25519(dir.path(), None, "me@laptop", None).unwrap();
+        assert_eq!(Path::new(&plain.private_key_path), dir.path().join("id_ed25519"));
+        assert_eq!(plain.algorithm, "ssh-ed25519");
+        assert_eq!(plain.comment, "me@laptop");
+        assert!(plain.fingerprint.starts_with("SHA256:"));
+        assert!(!plain.encrypted);
+        assert_eq!(remote_auth::key_is_encrypted(Path::new(&plain.private_key_path)), Some(false));
+        assert!(public_key(Path::new(&plain.private_key_path)).unwrap().starts_with("ssh-ed25519 "));
+
+        let protected = generate_ed25519(dir.path(), Some("work"), "me@work", Some("secret")).unwrap();
+        assert!(protected.encrypted);
+        assert_eq!(remote_auth::key_is_encrypted(Path::new(&protected.private_key_path)), Some(true));
+        let key = PrivateKey::read_openssh_file(Path::new(&protected.private_key_path)).unwrap();
+        assert!(key.decrypt("secret").is_ok());
+        assert!(key.decrypt("wrong").is_err());
+
+        // An empty passphrase means none
+        let empty = generate_ed25519(dir.path(), Some("empty"), "", Some("")).unwrap();
+        assert!(!empty.encrypted);
+    }
+
+    #[test]
+    fn never_overwrites_existing_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("id_ed25519"), "existing").unwrap();
+        fs::write(dir.path().join("other.pub"), "existing").unwrap();
+
+        for name in [None, Some("other")] {
+            let error = generate_ed25519(dir.path(), name, "", None).unwrap_err();
+            assert!(error.to_string().contains("already exists"), "{}", error);
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("id_ed25519")).unwrap(), "existing");
+        assert!(!dir.path().join("id_ed25519.pub").exists());
+        assert!(!dir.path().join("other").exists());
+    }
+
+    #[test]
+    fn rejects_key_names_outside_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for name in ["../escape", "sub/key", "sub\\key", ".hidden", "key.pub"] {
+            let error = generate_ed25519(dir.path(), Some(name), "", None).unwrap_err();
+            assert!(error.to_string().contains("Invalid key name"), "{}: {}", name, error);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // Blank names fall back to the default
+        let key = generate_ed25519(dir.path(), Some("  "), "", None).unwrap();
+        assert!(key.private_key_path.ends_with(DEFAULT_KEY_NAME));
+    }
+
+    #[test]
+    fn lists_public_keys_that_have_a_private_key() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(list_keys(&dir.path().join("missing")).unwrap().is_empty());
+
+        generate_ed25519(dir.path(), Some("b_key"), "b", None).unwrap();
+        generate_ed25519(dir.path(), Some("a_key"), "a", None).unwrap();
+        generate_ed25519(dir.path(), Some("orphan"), "", None).unwrap();
+        fs::remove_file(dir.path().join("orphan")).unwrap();
+        generate_ed25519(dir.path(), Some("private_only"), "", None).unwrap();
+        fs::remove_file(dir.path().join("private_only.pub")).unwrap();
+        fs::write(dir.path().join("known_hosts"), "").unwrap();
+
+        let keys = list_keys(dir.path()).unwrap();
+
+        let listed: Vec<_> = keys.iter().map(|key| (key.comment.as_str(), key.public_key_path.clone())).collect();
+        assert_eq!(listed, vec![
+            ("a", dir.path().join("a_key.pub").to_string_lossy().into_owned()),
+            ("b", dir.path().join("b_key.pub").to_string_lossy().into_owned()),
+        ]);
+    }
+}
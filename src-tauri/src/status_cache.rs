@@ -0,0 +1,263 @@
+use git2::{Repository, Status, StatusOptions};
+use parking_lot::Mutex;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+use crate::git_commands::{DiffStatus, GitError};
+use crate::watcher::ChangeBatch;
+
+// Batches touching more paths than this fall back to a full rescan
+const MAX_INCREMENTAL_PATHS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusEntry {
+    pub path: String,
+    pub status: DiffStatus,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepoStatus {
+    pub staged: Vec<StatusEntry>,
+    pub unstaged: Vec<StatusEntry>,
+    pub untracked: Vec<String>,
+    pub ignored: Vec<String>,
+    pub conflicted: Vec<String>,
+}
+
+// Working tree status per path, kept current from watcher batches so that
+// reading it never has to walk the whole working tree
+#[derive(Default)]
+pub struct StatusCache {
+    // None until the first full scan
+    entries: Option<HashMap<String, Status>>,
+    // Batches that arrived while nothing was cached. A scan that overlaps
+    // one of them may have missed its changes and isn't cached.
+    missed_batches: u64,
+}
+
+impl StatusCache {
+    fn replace_paths(&mut self, path: &str, statuses: Vec<(String, Status)>) {
+        if let Some(entries) = self.entries.as_mut() {
+            let dir_prefix = format!("{}/", path.trim_end_matches('/'));
+            entries.retain(|key, _| key != path && !key.starts_with(&dir_prefix));
+            entries.extend(statuses);
+        }
+    }
+}
+
+// Returns the cached status, running a full scan first if there is nothing cached
+pub fn snapshot(cache: &Mutex<StatusCache>, repo: &Repository) -> Result<RepoStatus, GitError> {
+    let missed_before = {
+        let cache = cache.lock();
+        if let Some(entries) = cache.entries.as_ref() {
+            return Ok(bucket_statuses(entries));
+        }
+        cache.missed_batches
+    };
+
+    let entries: HashMap<String, Status> = scan(repo, None)?.into_iter().collect();
+    let status = bucket_statuses(&entries);
+    let mut cache = cache.lock();
+    if cache.missed_batches == missed_before {
+        cache.entries = Some(entries);
+    }
+    Ok(status)
+}
+
+// Applies a watcher batch. Index, HEAD, ref and ignore rule changes can move
+// any path between buckets so they trigger a full rescan; plain file changes
+// only re-stat the touched paths.
+pub fn refresh(cache: &Mutex<StatusCache>, repo: &Repository, batch: &ChangeBatch) -> Result<(), GitError> {
+    {
+        let mut cache = cache.lock();
+        if cache.entries.is_none() {
+            // The next snapshot scans anyway, unless one is scanning right now
+            cache.missed_batches += 1;
+            return Ok(());
+        }
+    }
+
+    let paths = batch.worktree.len() + batch.ignored.len();
+    if !batch.git.is_empty() || batch.ignore_rules_changed || paths > MAX_INCREMENTAL_PATHS {
+        let entries = scan(repo, None)?.into_iter().collect();
+        cache.lock().entries = Some(entries);
+        return Ok(());
+    }
+
+    for path in batch.worktree.iter().chain(&batch.ignored) {
+        let statuses = scan(repo, Some(path))?;
+        cache.lock().replace_paths(path, statuses);
+    }
+    Ok(())
+}
+
+fn scan(repo: &Repository, pathspec: Option<&str>) -> Result<Vec<(String, Status)>, GitError> {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(true)
+        .recurse_ignored_dirs(false)
+        .exclude_submodules(false);
+    if let Some(path) = pathspec {
+        // Matched literally so file names with glob characters work; a
+        // directory still matches everything below it
+        opts.pathspec(path).disable_pathspec_match(true);
+    }
+
+    let statuses = repo.statuses(Some(&mut opts))?;
+    Ok(statuses.iter()
+        .filter_map(|entry| {
+            let path = entry.path()?.to_string();
+            Some((path, entry.status()))
+        })
+        .filter(|(_, status)| !status.is_empty())
+        .collect())
+}
+
+fn bucket_statuses(entries: &HashMap<String, Status>) -> RepoStatus {
+    let mut result = RepoStatus::default();
+
+    for (path, status) in entries {
+        if status.is_conflicted() {
+            result.conflicted.push(path.clone());
+            continue;
+        }
+        if status.is_ignored() {
+            result.ignored.push(path.clone());
+            continue;
+        }
+        if status.is_wt_new() {
+            result.untracked.push(path.clone());
+        }
+
+        // Renames aren't detected, since a rescan of a single path can't
+        // pair it with the other side; a move shows as a delete and an add
+        let staged = if status.is_index_new() {
+            Some(DiffStatus::Added)
+        } else if status.is_index_modified() {
+            Some(DiffStatus::Modified)
+        } else if status.is_index_deleted() {
+            Some(DiffStatus::Deleted)
+        } else if status.is_index_typechange() {
+            Some(DiffStatus::Typechange)
+        } else {
+            None
+        };
+        if let Some(status) = staged {
+            result.staged.push(StatusEntry { path: path.clone(), status });
+        }
+
+        let unstaged = if status.is_wt_modified() {
+            Some(DiffStatus::Modified)
+        } else if status.is_wt_deleted() {
+            Some(DiffStatus::Deleted)
+        } else if status.is_wt_typechange() {
+            Some(DiffStatus::Typechange)
+        } else {
+            None
+        };
+        if let Some(status) = unstaged {
+            result.unstaged.push(StatusEntry { path: path.clone(), status });
+        }
+    }
+
+    result.staged.sort_by(|a, b| a.path.cmp(&b.path));
+    result.unstaged.sort_by(|a, b| a.path.cmp(&b.path));
+    result.untracked.sort();
+    result.ignored.sort();
+    result.conflicted.sort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn repository() -> (tempfile::TempDir, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+        (dir, repo)
+    }
+
+    fn batch(worktree: &[&str], ignored: &[&str]) -> ChangeBatch {
+        ChangeBatch {
+            worktree: worktree.iter().map(|path| path.to_string()).collect(),
+            ignored: ignored.iter().map(|path| path.to_string()).collect(),
+            ..ChangeBatch::default()
+        }
+    }
+
+    #[test]
+    fn refreshes_ignored_paths() {
+        let (dir, repo) = repository();
+        let cache = Mutex::new(StatusCache::default());
+        assert_eq!(snapshot(&cache, &repo).unwrap().untracked, vec![".gitignore"]);
+
+        fs::write(dir.path().join("build.log"), "").unwrap();
+        refresh(&cache, &repo, &batch(&[], &["build.log"])).unwrap();
+        assert_eq!(snapshot(&cache, &repo).unwrap().ignored, vec!["build.log"]);
+
+        fs::remove_file(dir.path().join("build.log")).unwrap();
+        refresh(&cache, &repo, &batch(&[], &["build.log"])).unwrap();
+        assert!(snapshot(&cache, &repo).unwrap().ignored.is_empty());
+    }
+
+    #[test]
+    fn new_ignore_rules_rescan_everything() {
+        let (dir, repo) = repository();
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+        let cache = Mutex::new(StatusCache::default());
+        assert!(snapshot(&cache, &repo).unwrap().untracked.contains(&"notes.txt".to_string()));
+
+        fs::write(dir.path().join(".gitignore"), "*.log\n*.txt\n").unwrap();
+        let mut rules_changed = batch(&[".gitignore"], &[]);
+        rules_changed.ignore_rules_changed = true;
+        refresh(&cache, &repo, &rules_changed).unwrap();
+
+        let status = snapshot(&cache, &repo).unwrap();
+        assert_eq!(status.ignored, vec!["notes.txt"]);
+        assert_eq!(status.untracked, vec![".gitignore"]);
+    }
+
+    #[test]
+    fn batches_before_the_first_scan_are_not_lost() {
+        let (dir, repo) = repository();
+        let cache = Mutex::new(StatusCache::default());
+
+        fs::write(dir.path().join("new.txt"), "").unwrap();
+        refresh(&cache, &repo, &batch(&["new.txt"], &[])).unwrap();
+        assert_eq!(cache.lock().missed_batches, 1);
+
+        assert!(snapshot(&cache, &repo).unwrap().untracked.contains(&"new.txt".to_string()));
+        assert!(cache.lock().entries.is_some());
+    }
+
+    #[test]
+    fn staged_moves_show_as_a_delete_and_an_add() {
+        let (dir, repo) = repository();
+        fs::write(dir.path().join("old.txt"), "content\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("old.txt")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Add old.txt", &tree, &[]).unwrap();
+
+        // git mv old.txt new.txt
+        fs::rename(dir.path().join("old.txt"), dir.path().join("new.txt")).unwrap();
+        index.remove_path(Path::new("old.txt")).unwrap();
+        index.add_path(Path::new("new.txt")).unwrap();
+        index.write().unwrap();
+
+        let cache = Mutex::new(StatusCache::default());
+        let staged: Vec<_> = snapshot(&cache, &repo).unwrap().staged.into_iter()
+            .map(|entry| (entry.path, entry.status))
+            .collect();
+        assert_eq!(staged, vec![
+            ("new.txt".to_string(), DiffStatus::Added),
+            ("old.txt".to_string(), DiffStatus::Deleted),
+        ]);
+    }
+}
@@ -0,0 +1,160 @@
+use git2::Oid;
+use parking_lot::Mutex;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use syntect::easy::ScopeRangeIterator;
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+// Highlighted blobs kept in memory before the cache is reset
+const MAX_CACHED_BLOBS: usize = 256;
+
+// Parsing stops at the first line longer than this; the rest stays plain
+const MAX_LINE_BYTES: usize = 10_000;
+
+// A highlighted range of a line, in UTF-16 code units like ChangeSpan, with
+// the innermost scope name (e.g. "keyword.control.rust")
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenSpan {
+    pub start: usize,
+    pub end: usize,
+    pub scope: String,
+}
+
+pub type LineTokens = Vec<Vec<TokenSpan>>;
+
+struct Highlighter {
+    syntaxes: SyntaxSet,
+    // Keyed by blob id and syntax name, as the same blob can be highlighted
+    // differently under another file name
+    cache: Mutex<HashMap<(Oid, String), Arc<LineTokens>>>,
+}
+
+fn highlighter() -> &'static Highlighter {
+    static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+    HIGHLIGHTER.get_or_init(|| Highlighter {
+        syntaxes: SyntaxSet::load_defaults_newlines(),
+        cache: Mutex::new(HashMap::new()),
+    })
+}
+
+// Tokenises a blob's lines based on its file extension (or first line).
+// Results are cached by blob id and syntax, so the same blob is only parsed
+// once per language.
+pub fn highlight_blob(id: Oid, path: &Path, content: &[u8]) -> Option<Arc<LineTokens>> {
+    let highlighter = highlighter();
+    let text = String::from_utf8_lossy(content);
+    let syntax = find_syntax(&highlighter.syntaxes, path, &text)?;
+    let key = (id, syntax.name.clone());
+    if let Some(tokens) = highlighter.cache.lock().get(&key) {
+        return Some(tokens.clone());
+    }
+
+    let tokens = Arc::new(highlight_text(&highlighter.syntaxes, syntax, &text));
+
+    let mut cache = highlighter.cache.lock();
+    if cache.len() >= MAX_CACHED_BLOBS {
+        cache.clear();
+    }
+    cache.insert(key, tokens.clone());
+    Some(tokens)
+}
+
+fn find_syntax<'a>(syntaxes: &'a SyntaxSet, path: &Path, text: &str) -> Option<&'a SyntaxReference> {
+    let by_extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| syntaxes.find_syntax_by_extension(ext));
+    let by_name = || path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| syntaxes.find_syntax_by_extension(name));
+    let by_first_line = || text.lines().next()
+        .and_then(|line| syntaxes.find_syntax_by_first_line(line));
+
+    by_extension.or_else(by_name).or_else(by_first_line)
+}
+
+fn highlight_text(syntaxes: &SyntaxSet, syntax: &SyntaxReference, text: &str) -> LineTokens {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut lines = Vec::new();
+
+    for line in text.split_inclusive('\n') {
+        if line.len() > MAX_LINE_BYTES {
+            break;
+        }
+        let ops = match state.parse_line(line, syntaxes) {
+            Ok(ops) => ops,
+            Err(_) => break,
+        };
+
+        let mut tokens: Vec<TokenSpan> = Vec::new();
+        let mut pos = 0;
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            if stack.apply(op).is_err() {
+                break;
+            }
+            let text = line[range].trim_end_matches(&['\r', '\n'][..]);
+            if text.is_empty() {
+                continue;
+            }
+
+            let start = pos;
+            pos += text.encode_utf16().count();
+
+            // Use the innermost scope that says something about the token;
+            // text under only the root or structural "meta" scopes stays plain
+            let scope = stack.as_slice()
+                .iter()
+                .skip(1)
+                .rev()
+                .map(|scope| scope.build_string())
+                .find(|scope| !scope.starts_with("meta."));
+            let scope = match scope {
+                Some(scope) => scope,
+                None => continue,
+            };
+
+            match tokens.last_mut() {
+                Some(last) if last.end == start && last.scope == scope => last.end = pos,
+                _ => tokens.push(TokenSpan { start, end: pos, scope }),
+            }
+        }
+        lines.push(tokens);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_the_same_blob_per_syntax() {
+        let content = b"# comment\nfn main() {}\n";
+        let id = Oid::hash_object(git2::ObjectType::Blob, content).unwrap();
+
+        let python = highlight_blob(id, Path::new("script.py"), content).unwrap();
+        let rust = highlight_blob(id, Path::new("main.rs"), content).unwrap();
+
+        assert!(python[0].iter().any(|token| token.scope.starts_with("comment.")), "{:?}", python);
+        assert!(!rust[0].iter().any(|token| token.scope.starts_with("comment.")), "{:?}", rust);
+        assert!(Arc::ptr_eq(&python, &highlight_blob(id, Path::new("other.py"), content).unwrap()));
+        assert!(highlight_blob(id, Path::new("notes.unknown-extension"), content).is_none());
+    }
+
+    #[test]
+    fn counts_offsets_in_utf16_code_units() {
+        let content = "let s = \"😀\"; // done\n";
+        let id = Oid::hash_object(git2::ObjectType::Blob, content.as_bytes()).unwrap();
+
+        let tokens = highlight_blob(id, Path::new("main.rs"), content.as_bytes()).unwrap();
+
+        // The comment's text after the `//` marker
+        let comment = tokens[0].iter().find(|token| token.scope.starts_with("comment.")).unwrap();
+        let start = content.find(" done").unwrap();
+        assert_eq!(comment.start, content[..start].encode_utf16().count());
+        assert_eq!(comment.end, content.trim_end().encode_utf16().count());
+    }
+}
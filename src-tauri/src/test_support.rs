@@ -0,0 +1,163 @@
+// Helpers shared by the unit tests: a stand-in HTTP server for the hosting
+// APIs and OAuth endpoints, and an account registry in a temp dir
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::accounts::AccountRegistry;
+use crate::credentials::EncryptedFileStore;
+
+#[derive(Debug, Clone)]
+pub struct StubRequest {
+    pub method: String,
+    // Path and query, e.g. /user/repos?page=2
+    pub target: String,
+    // Names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub received_at: tokio::time::Instant,
+}
+
+impl StubRequest {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<String> {
+        let (_, query) = self.target.split_once('?')?;
+        pairs(query, name)
+    }
+
+    pub fn form(&self, name: &str) -> Option<String> {
+        pairs(&self.body, name)
+    }
+}
+
+fn pairs(encoded: &str, name: &str) -> Option<String> {
+    oauth2::url::form_urlencoded::parse(encoded.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+#[derive(Debug, Clone)]
+pub struct StubResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl StubResponse {
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        StubResponse {
+            status,
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn empty(status: u16) -> Self {
+        StubResponse { status, headers: Vec::new(), body: String::new() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&StubRequest) -> StubResponse + Send + Sync;
+
+// HTTP/1.1 on an ephemeral localhost port. Every request is answered by the
+// handler and recorded; connections are closed after one response.
+pub struct StubServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl StubServer {
+    pub async fn start(handler: impl Fn(&StubRequest) -> StubResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind stub server");
+        let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let recorded = recorded.clone();
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let _ = serve(stream, &*handler, &recorded).await;
+                });
+            }
+        });
+        StubServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().clone()
+    }
+}
+
+async fn serve(
+    mut stream: tokio::net::TcpStream,
+    handler: &Handler,
+    recorded: &Mutex<Vec<StubRequest>>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let length = headers.iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    let request = StubRequest {
+        method,
+        target,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+        received_at: tokio::time::Instant::now(),
+    };
+    let response = handler(&request);
+    recorded.lock().push(request);
+
+    let mut head = format!("HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// An empty registry whose tokens go to an encrypted file in `dir`
+pub fn account_registry(dir: &std::path::Path) -> Arc<Mutex<AccountRegistry>> {
+    let store = EncryptedFileStore::with_key(dir.join("credentials.vault"), [7; 32]);
+    let registry = AccountRegistry::load(dir, Arc::new(store)).expect("load account registry");
+    Arc::new(Mutex::new(registry))
+}
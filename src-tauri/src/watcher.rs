@@ -0,0 +1,294 @@
+use git2::Repository;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::git_commands::GitError;
+
+// Quiet period that ends a batch of file system events
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+// Upper bound on how long a constant stream of events can delay a batch
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(2);
+
+// Files under .git that affect what HEAD, the branches or the index look like
+const GIT_STATE_ENTRIES: &[&str] = &[
+    "HEAD",
+    "ORIG_HEAD",
+    "FETCH_HEAD",
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "index",
+    "packed-refs",
+    "refs",
+];
+
+// Payload of the `repo-changed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoChangedEvent {
+    pub repo_path: String,
+    pub paths: Vec<String>,
+}
+
+// Payload of the `refs-changed` event, paths are relative to the .git directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefsChangedEvent {
+    pub repo_path: String,
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ChangeBatch {
+    // Working tree paths relative to the repository root, gitignored ones excluded
+    pub worktree: Vec<String>,
+    // Gitignored paths, each reduced to its outermost ignored directory so
+    // that a build writing thousands of files counts once. Only the status
+    // cache needs these.
+    pub ignored: Vec<String>,
+    // HEAD, ref and index paths relative to the .git directory
+    pub git: Vec<String>,
+    // A .gitignore or .git/info/exclude changed, which can move any path
+    // in or out of the ignored bucket
+    pub ignore_rules_changed: bool,
+}
+
+impl ChangeBatch {
+    pub fn is_empty(&self) -> bool {
+        self.worktree.is_empty() && self.ignored.is_empty() && self.git.is_empty() && !self.ignore_rules_changed
+    }
+}
+
+// Watches the repository recursively and calls `on_change` with a debounced,
+// filtered batch of changes. The background thread exits once the returned
+// watcher is dropped.
+pub fn watch_repository<F>(root: &Path, on_change: F) -> Result<Box<dyn Watcher + Send>, GitError>
+where
+    F: FnMut(ChangeBatch) + Send + 'static,
+{
+    // Events on macOS carry canonical paths (/private/var for /var), and
+    // other backends report paths under the watched one, so watch and match
+    // against the canonical root
+    let root = std::fs::canonicalize(root)?;
+    let (tx, rx) = channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    }).map_err(|e| GitError::Custom(e.to_string()))?;
+
+    watcher.watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| GitError::Custom(e.to_string()))?;
+
+    thread::spawn(move || run_event_loop(root, rx, on_change));
+
+    Ok(Box::new(watcher))
+}
+
+fn run_event_loop<F: FnMut(ChangeBatch)>(root: PathBuf, rx: Receiver<Event>, mut on_change: F) {
+    // A separate handle for gitignore lookups, the GitRepo's one can't be shared
+    let repo = Repository::open(&root).ok();
+    let git_dir = repo.as_ref()
+        .map(|repo| repo.path().to_path_buf())
+        .unwrap_or_else(|| root.join(".git"));
+    let git_dir = std::fs::canonicalize(&git_dir).unwrap_or(git_dir);
+
+    while let Ok(first) = rx.recv() {
+        let mut events = vec![first];
+        let started = Instant::now();
+        let mut disconnected = false;
+
+        while started.elapsed() < MAX_BATCH_DELAY {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(event) => events.push(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        let batch = classify_events(&root, &git_dir, repo.as_ref(), &events);
+        if !batch.is_empty() {
+            on_change(batch);
+        }
+        if disconnected {
+            break;
+        }
+    }
+}
+
+fn classify_events(root: &Path, git_dir: &Path, repo: Option<&Repository>, events: &[Event]) -> ChangeBatch {
+    let mut worktree = BTreeSet::new();
+    let mut ignored = BTreeSet::new();
+    let mut git = BTreeSet::new();
+    let mut ignore_rules_changed = false;
+
+    let changed_paths = events.iter()
+        .filter(|event| matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ))
+        .flat_map(|event| event.paths.iter());
+
+    for path in changed_paths {
+        if let Ok(relative) = path.strip_prefix(git_dir) {
+            if relative == Path::new("info/exclude") {
+                ignore_rules_changed = true;
+            } else if is_git_state_path(relative) {
+                git.insert(to_slash_path(relative));
+            }
+            continue;
+        }
+
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => continue,
+        };
+        // Catches worktrees whose .git directory isn't where libgit2 reported it
+        if relative.components().next() == Some(Component::Normal(".git".as_ref())) {
+            continue;
+        }
+        if relative.file_name() == Some(".gitignore".as_ref()) {
+            ignore_rules_changed = true;
+        }
+        match repo.and_then(|repo| outermost_ignored(repo, relative)) {
+            Some(ignored_root) => ignored.insert(ignored_root),
+            None => worktree.insert(to_slash_path(relative)),
+        };
+    }
+
+    ChangeBatch {
+        worktree: worktree.into_iter().collect(),
+        ignored: ignored.into_iter().collect(),
+        git: git.into_iter().collect(),
+        ignore_rules_changed,
+    }
+}
+
+// The shortest prefix of `relative` that is gitignored, if any. Directories
+// are checked with a trailing slash, as rules like `target/` only match
+// directories and the path may already be gone.
+fn outermost_ignored(repo: &Repository, relative: &Path) -> Option<String> {
+    let mut prefix = PathBuf::new();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        prefix.push(component);
+        let slash_path = to_slash_path(&prefix);
+        let candidate = if components.peek().is_some() {
+            format!("{}/", slash_path)
+        } else {
+            slash_path.clone()
+        };
+        if repo.is_path_ignored(&candidate).unwrap_or(false) {
+            return Some(slash_path);
+        }
+    }
+    None
+}
+
+fn is_git_state_path(relative: &Path) -> bool {
+    // Lock files come and go on every write; the rename onto the real file
+    // produces its own event
+    if relative.extension().map_or(false, |ext| ext == "lock") {
+        return false;
+    }
+    match relative.components().next() {
+        Some(Component::Normal(first)) => GIT_STATE_ENTRIES.iter().any(|entry| first == *entry),
+        _ => false,
+    }
+}
+
+fn to_slash_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::ModifyKind;
+    use std::fs;
+
+    fn repository() -> (tempfile::TempDir, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n*.log\n").unwrap();
+        (dir, repo)
+    }
+
+    fn classify(repo: &Repository, paths: &[PathBuf]) -> ChangeBatch {
+        let root = repo.workdir().unwrap();
+        let events: Vec<Event> = paths.iter()
+            .map(|path| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone()))
+            .collect();
+        classify_events(root, repo.path(), Some(repo), &events)
+    }
+
+    #[test]
+    fn separates_worktree_git_and_ignored_paths() {
+        let (dir, repo) = repository();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target/debug/deps")).unwrap();
+
+        let batch = classify(&repo, &[
+            root.join("src/main.rs"),
+            root.join("target/debug/deps/a.o"),
+            root.join("target/debug/deps/b.o"),
+            root.join("logs/build.log"),
+            repo.path().join("refs/heads/main"),
+            repo.path().join("index.lock"),
+            repo.path().join("objects/ab/cdef"),
+        ]);
+
+        assert_eq!(batch.worktree, vec!["src/main.rs"]);
+        assert_eq!(batch.ignored, vec!["logs/build.log", "target"]);
+        assert_eq!(batch.git, vec!["refs/heads/main"]);
+        assert!(!batch.ignore_rules_changed);
+    }
+
+    #[test]
+    fn ignore_files_change_the_ignore_rules() {
+        let (dir, repo) = repository();
+
+        let batch = classify(&repo, &[dir.path().join("docs/.gitignore")]);
+        assert!(batch.ignore_rules_changed);
+        assert_eq!(batch.worktree, vec!["docs/.gitignore"]);
+
+        let batch = classify(&repo, &[repo.path().join("info/exclude")]);
+        assert!(batch.ignore_rules_changed && batch.git.is_empty());
+        assert!(!batch.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_changes_under_a_symlinked_root() {
+        let (dir, repo) = repository();
+        let link_dir = tempfile::tempdir().unwrap();
+        let link = link_dir.path().join("repo");
+        std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+        let (tx, rx) = channel();
+
+        let _watcher = watch_repository(&link, move |batch| {
+            let _ = tx.send(batch);
+        }).unwrap();
+        fs::write(dir.path().join("file.txt"), "changed").unwrap();
+        repo.set_head("refs/heads/other").unwrap();
+
+        let (mut worktree, mut git) = (BTreeSet::new(), BTreeSet::new());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !(worktree.contains("file.txt") && git.contains("HEAD")) {
+            let batch = rx.recv_timeout(deadline.saturating_duration_since(Instant::now())).expect("no change reported");
+            worktree.extend(batch.worktree);
+            git.extend(batch.git);
+        }
+    }
+}
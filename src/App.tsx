@@ -101,6 +101,34 @@ function App() {
           setMessage(`Authentication failed: ${event.payload}`);
         }),
 
+        await listen("device-code", (event: any) => {
+          setMessage(
+            `Enter code ${event.payload.user_code} at ${event.payload.verification_uri} to sign in`
+          );
+        }),
+
+        await listen("ssh-passphrase-required", (event: any) => {
+          const passphrase = window.prompt(
+            `Enter the passphrase for ${event.payload.key_path}`
+          );
+          invoke("answer_ssh_passphrase", {
+            requestId: event.payload.request_id,
+            passphrase,
+          }).catch(console.error);
+        }),
+
+        await listen("ssh-host-key-unknown", (event: any) => {
+          const accept = window.confirm(
+            `The authenticity of host ${event.payload.host} can't be established.\n` +
+              `${event.payload.algorithm} key fingerprint is ${event.payload.fingerprint}.\n` +
+              `Do you want to trust this host and continue connecting?`
+          );
+          invoke("answer_ssh_host_key", {
+            requestId: event.payload.request_id,
+            accept,
+          }).catch(console.error);
+        }),
+
         await listen("auth-timeout", () => {
           setIsAuthenticated(false);
           setIsAuthenticating(false);
@@ -187,12 +215,6 @@ function App() {
       if (result === "Authentication successful") {
         setIsAuthenticated(true);
 
-        // Get the token from the auth state
-        const token = localStorage.getItem("github_token");
-        if (token) {
-          await invoke("set_github_token", { token });
-        }
-
         // Fetch repositories again after successful authentication
         try {
           const githubRepos = await invoke<string[]>("list_github_repos");
@@ -286,10 +308,11 @@ function App() {
     }
   };
 
-  const handleRepositoryCloned = () => {
+  // `repo.path` is the local clone, which every git command expects
+  const handleRepositoryCloned = (repo: { path: string; name: string }) => {
     setLocalRepository({
-      path: repoPath,
-      name: repoPath.split("/").pop() || repoPath,
+      path: repo.path,
+      name: repo.name,
       stats: { commits: 0, branches: 0, contributors: 0 },
       isLocal: true,
     });
@@ -312,45 +335,47 @@ function App() {
   }, [repositories]);
 
   const checkAuth = async () => {
-    const token = localStorage.getItem("github_token");
-    if (token) {
-      try {
-        const isValid = await invoke<boolean>("validate_github_token", {
-          token,
-        });
-        if (isValid) {
-          await invoke("set_github_token", { token });
-          setIsAuthenticated(true);
+    // Tokens used to live in localStorage; hand any leftover one to the
+    // backend's credential store and drop it from here
+    const legacyToken = localStorage.getItem("github_token");
+    if (legacyToken) {
+      localStorage.removeItem("github_token");
+      await invoke("set_github_token", { token: legacyToken }).catch(
+        console.error
+      );
+    }
 
-          // Fetch repositories after successful auth
-          try {
-            const githubRepos = await invoke<string[]>("list_github_repos");
-            const pinnedRepos = loadPinnedRepos();
-
-            const repositories: Repository[] = githubRepos.map((repoName) => ({
-              name: repoName.split("/").pop() || repoName,
-              path: repoName,
-              isPinned: pinnedRepos.includes(repoName),
-              stats: {
-                commits: 0,
-                branches: 0,
-                contributors: 0,
-              },
-            }));
-
-            setRepositories(repositories);
-          } catch (error) {
-            console.error("Failed to fetch repositories:", error);
-          }
-        } else {
-          localStorage.removeItem("github_token");
-          setIsAuthenticated(false);
+    try {
+      const isValid = await invoke<boolean>("check_stored_token");
+      if (isValid) {
+        setIsAuthenticated(true);
+
+        // Fetch repositories after successful auth
+        try {
+          const githubRepos = await invoke<string[]>("list_github_repos");
+          const pinnedRepos = loadPinnedRepos();
+
+          const repositories: Repository[] = githubRepos.map((repoName) => ({
+            name: repoName.split("/").pop() || repoName,
+            path: repoName,
+            isPinned: pinnedRepos.includes(repoName),
+            stats: {
+              commits: 0,
+              branches: 0,
+              contributors: 0,
+            },
+          }));
+
+          setRepositories(repositories);
+        } catch (error) {
+          console.error("Failed to fetch repositories:", error);
         }
-      } catch (error) {
-        console.error("Auth check failed:", error);
-        localStorage.removeItem("github_token");
+      } else {
         setIsAuthenticated(false);
       }
+    } catch (error) {
+      console.error("Auth check failed:", error);
+      setIsAuthenticated(false);
     }
   };
 
@@ -69,7 +69,7 @@ export const AdvancedOperations: React.FC<OperationProps> = ({
     try {
       setIsStashing(true);
       const result = await invoke<string>("stash_changes", {
-        path: repoPath,
+        path: localRepository?.path,
       });
       showFeedback("success", result);
     } catch (error) {
@@ -82,7 +82,7 @@ export const AdvancedOperations: React.FC<OperationProps> = ({
   const handleStashPop = async () => {
     try {
       const result = await invoke<string>("stash_pop", {
-        path: repoPath,
+        path: localRepository?.path,
       });
       showFeedback("success", result);
     } catch (error) {
@@ -99,7 +99,7 @@ export const AdvancedOperations: React.FC<OperationProps> = ({
     try {
       setIsTagging(true);
       const result = await invoke<string>("create_tag", {
-        path: repoPath,
+        path: localRepository?.path,
         tagName,
         message: tagMessage,
       });
@@ -123,7 +123,7 @@ export const AdvancedOperations: React.FC<OperationProps> = ({
     try {
       setIsResetting(true);
       const result = await invoke<string>("reset_hard", {
-        path: repoPath,
+        path: localRepository?.path,
         commitHash: resetCommitHash,
       });
       showFeedback("success", result);
@@ -139,7 +139,7 @@ export const AdvancedOperations: React.FC<OperationProps> = ({
   const handleListRemotes = async () => {
     try {
       const result = await invoke<string>("list_remotes", {
-        path: repoPath,
+        path: localRepository?.path,
       });
       showFeedback("info", result);
     } catch (error) {
@@ -44,26 +44,11 @@ export const BasicOperations: React.FC<OperationProps> = ({
       setIsCloning(true);
       showFeedback("info", "Cloning repository...");
 
-      const token = localStorage.getItem("github_token");
-      if (!token) {
-        showFeedback(
-          "error",
-          "Not authenticated with GitHub. Please login first."
-        );
-        return;
-      }
-
-      await invoke("set_github_token", { token });
-
-      const customDir = localStorage.getItem("clone_directory");
       const repoName = repoPath.split("/").pop();
-      const localPath = customDir
-        ? `${customDir}/${repoName}`
-        : `${await invoke<string>("get_home_dir")}/simplegit/${repoName}`;
-
-      const result = await invoke<string>("clone_repository", {
-        repoUrl: `https://github.com/${repoPath}.git`,
-        path: localPath,
+      const webUrl = await invoke<string>("get_github_web_url");
+      // The backend picks the clone directory and returns where it cloned to
+      const localPath = await invoke<string>("clone_repository", {
+        repoUrl: `${webUrl}/${repoPath}.git`,
       });
 
       if (onRepositoryCloned) {
@@ -73,7 +58,7 @@ export const BasicOperations: React.FC<OperationProps> = ({
         });
       }
 
-      showFeedback("success", result);
+      showFeedback("success", `Repository cloned successfully to ${localPath}`);
     } catch (error) {
       console.error("Clone error:", error);
       showFeedback("error", `Error cloning repository: ${error}`);
@@ -99,7 +84,9 @@ export const BasicOperations: React.FC<OperationProps> = ({
       const isLocal = localRepository && localRepository.path === repoPath;
       const result = await invoke<string>(
         isLocal ? "push_changes" : "push_changes_remote",
-        { path: repoPath }
+        isLocal
+          ? { path: localRepository.path }
+          : { repoPath: localRepository.path }
       );
       showFeedback("success", result);
     } catch (error) {
@@ -123,7 +110,7 @@ export const BasicOperations: React.FC<OperationProps> = ({
     try {
       setIsPulling(true);
       const result = await invoke<string>("pull_changes", {
-        path: repoPath,
+        path: localRepository.path,
         isRemote: !localRepository,
       });
       showFeedback("success", result);
@@ -56,7 +56,7 @@ export const BranchOperations: React.FC<OperationProps> = ({
     try {
       setIsCreatingBranch(true);
       const result = await invoke<string>("create_branch", {
-        path: repoPath,
+        path: localRepository?.path,
         branchName,
       });
       showFeedback("success", result);
@@ -78,7 +78,7 @@ export const BranchOperations: React.FC<OperationProps> = ({
     try {
       setIsCheckingOut(true);
       const result = await invoke<string>("checkout_branch", {
-        path: repoPath,
+        path: localRepository?.path,
         branchName: targetBranch,
       });
       showFeedback("success", result);
@@ -100,7 +100,7 @@ export const BranchOperations: React.FC<OperationProps> = ({
     try {
       setIsMerging(true);
       const result = await invoke<string>("merge_branch", {
-        path: repoPath,
+        path: localRepository?.path,
         branchName: targetBranch,
       });
       showFeedback("success", result);
@@ -121,7 +121,7 @@ export const BranchOperations: React.FC<OperationProps> = ({
 
     try {
       const result = await invoke<string>("delete_branch", {
-        path: repoPath,
+        path: localRepository?.path,
         branchName: targetBranch,
       });
       showFeedback("success", result);
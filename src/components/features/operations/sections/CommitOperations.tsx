@@ -46,7 +46,7 @@ export const CommitOperations: React.FC<OperationProps> = ({
 
   const handleStage = async () => {
     try {
-      const result = await invoke<string>("stage_changes", { path: repoPath });
+      const result = await invoke<string>("stage_changes", { path: localRepository?.path });
       showFeedback("success", result);
     } catch (error) {
       showFeedback("error", `Error staging changes: ${error}`);
@@ -62,7 +62,7 @@ export const CommitOperations: React.FC<OperationProps> = ({
     try {
       setIsCommitting(true);
       const result = await invoke<string>("commit_changes", {
-        path: repoPath,
+        path: localRepository?.path,
         message: commitMessage,
       });
       showFeedback("success", result);
@@ -85,10 +85,9 @@ export const CommitOperations: React.FC<OperationProps> = ({
       setIsReverting(true);
       const result = await invoke<string>(
         localRepository ? "revert_commit" : "revert_commit_remote",
-        {
-          path: repoPath,
-          commitHash,
-        }
+        localRepository
+          ? { path: localRepository.path, commitHash }
+          : { repoPath, commitHash }
       );
       showFeedback("success", result);
       setShowRevertDialog(false);
@@ -103,7 +102,7 @@ export const CommitOperations: React.FC<OperationProps> = ({
   const handleViewLog = async () => {
     try {
       const result = await invoke<string>("view_commit_log", {
-        path: repoPath,
+        path: localRepository?.path,
       });
       showFeedback("info", result);
     } catch (error) {
@@ -113,7 +112,7 @@ export const CommitOperations: React.FC<OperationProps> = ({
 
   const handleAmend = async () => {
     try {
-      const result = await invoke<string>("amend_commit", { path: repoPath });
+      const result = await invoke<string>("amend_commit", { path: localRepository?.path });
       showFeedback("success", result);
     } catch (error) {
       showFeedback("error", `Error amending commit: ${error}`);
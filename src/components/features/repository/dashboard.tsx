@@ -212,9 +212,10 @@ export const RepositoryDashboard: React.FC<{
                 <Tooltip>
                   <TooltipTrigger asChild>
                     <button
-                      onClick={(e) => {
+                      onClick={async (e) => {
                         e.stopPropagation();
-                        open(`https://github.com/${repo.path}`);
+                        const webUrl = await invoke<string>("get_github_web_url");
+                        open(`${webUrl}/${repo.path}`);
                       }}
                       className="w-8 h-8 p-0 flex items-center justify-center hover:text-primary transition-colors"
                       aria-label="Open in browser"
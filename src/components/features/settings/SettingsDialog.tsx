@@ -1,6 +1,7 @@
 import React, { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
 import { dialog } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event";
 import {
   Dialog,
   DialogContent,
@@ -33,20 +34,31 @@ export const SettingsDialog: React.FC<SettingsDialogProps> = ({
 }) => {
   const [cloneDir, setCloneDir] = useState<string>("");
 
-  // Load the current clone directory on component mount
+  // The clone directory is part of the backend settings; reload it whenever
+  // they change
   useEffect(() => {
     const loadCloneDir = async () => {
       try {
-        const homeDir = await invoke<string>("get_home_dir");
-        const savedDir = localStorage.getItem("clone_directory") || `${homeDir}/simplegit`;
-        setCloneDir(savedDir);
+        setCloneDir(await invoke<string>("get_clone_directory"));
       } catch (error) {
         console.error("Error loading clone directory:", error);
       }
     };
     loadCloneDir();
+    const unlisten = listen("settings-changed", loadCloneDir);
+    return () => {
+      unlisten.then((stop) => stop());
+    };
   }, []);
 
+  const saveCloneDir = async (path: string) => {
+    try {
+      await invoke("set_clone_directory", { path });
+    } catch (error) {
+      console.error("Error saving clone directory:", error);
+    }
+  };
+
   const handleSelectDirectory = async () => {
     try {
       const selected = await dialog.open({
@@ -56,9 +68,7 @@ export const SettingsDialog: React.FC<SettingsDialogProps> = ({
 
       if (selected && typeof selected === "string") {
         setCloneDir(selected);
-        localStorage.setItem("clone_directory", selected);
-        // Notify the backend of the new directory
-        await invoke("set_clone_directory", { path: selected });
+        await saveCloneDir(selected);
       }
     } catch (error) {
       console.error("Error selecting directory:", error);
@@ -78,6 +88,7 @@ export const SettingsDialog: React.FC<SettingsDialogProps> = ({
               <Input
                 value={cloneDir}
                 onChange={(e) => setCloneDir(e.target.value)}
+                onBlur={() => cloneDir.trim() && saveCloneDir(cloneDir.trim())}
                 placeholder="Repository clone location"
                 className="flex-1"
               />